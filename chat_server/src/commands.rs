@@ -0,0 +1,184 @@
+mod builtin;
+mod webhook;
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{config::CommandConfig, AppError, User};
+use builtin::Builtin;
+use webhook::WebhookCommand;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// who invoked the command and in which chat
+#[derive(Debug)]
+pub(crate) struct CommandContext<'a> {
+    pub user: &'a User,
+    pub chat_id: i64,
+}
+
+/// result of a slash command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommandOutput {
+    /// only returned to the caller, never stored
+    Ephemeral { text: String },
+    /// stored as a regular message sent by the caller
+    Persisted { content: String },
+}
+
+/// response body for ephemeral command output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EphemeralMessage {
+    pub chat_id: i64,
+    pub content: String,
+    pub ephemeral: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+enum Command {
+    Builtin(Builtin),
+    Webhook(WebhookCommand),
+}
+
+#[derive(Debug)]
+pub(crate) struct CommandRegistry {
+    commands: HashMap<String, Command>,
+    client: reqwest::Client,
+}
+
+/// split `/name args` into (name, args). `//text` escapes a literal slash and is not a command.
+pub(crate) fn parse(content: &str) -> Option<(&str, &str)> {
+    let rest = content.strip_prefix('/')?;
+    if rest.starts_with('/') {
+        return None;
+    }
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, args.trim()))
+}
+
+/// `//text` is stored as `/text`
+pub(crate) fn unescape(content: &mut String) {
+    if content.starts_with("//") {
+        content.remove(0);
+    }
+}
+
+impl CommandRegistry {
+    pub fn new(config: &CommandConfig) -> Result<Self, AppError> {
+        let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build()?;
+        let mut commands = HashMap::new();
+        for builtin in Builtin::ALL {
+            commands.insert(builtin.name().to_string(), Command::Builtin(*builtin));
+        }
+        for hook in &config.webhooks {
+            if commands.contains_key(&hook.name) {
+                warn!("webhook command /{} conflicts with a builtin, ignored", hook.name);
+                continue;
+            }
+            commands.insert(hook.name.clone(), Command::Webhook(WebhookCommand::new(hook)));
+        }
+        Ok(Self { commands, client })
+    }
+
+    /// input naming no registered command is sent as plain text, e.g. a path like `/usr/bin`
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands.contains_key(name)
+    }
+
+    pub async fn dispatch(
+        &self,
+        name: &str,
+        args: &str,
+        ctx: &CommandContext<'_>,
+    ) -> Result<CommandOutput, AppError> {
+        match self.commands.get(name) {
+            Some(Command::Builtin(Builtin::Help)) => Ok(CommandOutput::Ephemeral { text: self.help() }),
+            Some(Command::Builtin(builtin)) => builtin.run(args, ctx),
+            Some(Command::Webhook(hook)) => hook.call(&self.client, name, args, ctx).await,
+            None => Err(AppError::CommandError(format!("unknown command: /{}", name))),
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut lines: Vec<_> = self
+            .commands
+            .iter()
+            .map(|(name, cmd)| {
+                let desc = match cmd {
+                    Command::Builtin(b) => b.description(),
+                    Command::Webhook(w) => w.description(),
+                };
+                format!("/{} - {}", name, desc)
+            })
+            .collect();
+        lines.sort();
+        lines.join("\n")
+    }
+}
+
+impl EphemeralMessage {
+    pub fn new(chat_id: i64, content: String) -> Self {
+        Self {
+            chat_id,
+            content,
+            ephemeral: true,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn parse_should_work() {
+        assert_eq!(parse("/shrug"), Some(("shrug", "")));
+        assert_eq!(parse("/me  waves hello "), Some(("me", "waves hello")));
+        assert_eq!(parse("//shrug"), None);
+        assert_eq!(parse("/ shrug"), None);
+        assert_eq!(parse("hello /shrug"), None);
+
+        let mut content = "//shrug".to_string();
+        unescape(&mut content);
+        assert_eq!(content, "/shrug");
+    }
+
+    #[tokio::test]
+    async fn dispatch_should_work() -> Result<()> {
+        let registry = CommandRegistry::new(&CommandConfig::default())?;
+        let user = User::new(1, "vincent", "vincent@test.com");
+        let ctx = CommandContext {
+            user: &user,
+            chat_id: 1,
+        };
+
+        let ret = registry.dispatch("help", "", &ctx).await?;
+        assert!(matches!(ret, CommandOutput::Ephemeral { text } if text.contains("/shrug")));
+
+        let ret = registry.dispatch("shrug", "ok", &ctx).await?;
+        assert_eq!(
+            ret,
+            CommandOutput::Persisted {
+                content: "ok ¯\\_(ツ)_/¯".to_string()
+            }
+        );
+
+        assert!(registry.contains("shrug"));
+        assert!(!registry.contains("usr"));
+        let ret = registry.dispatch("unknown", "", &ctx).await;
+        assert!(matches!(ret, Err(AppError::CommandError(_))));
+
+        Ok(())
+    }
+}
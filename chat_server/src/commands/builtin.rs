@@ -0,0 +1,141 @@
+use crate::AppError;
+
+use super::{CommandContext, CommandOutput};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Builtin {
+    Help,
+    Me,
+    Shrug,
+    Poll,
+}
+
+impl Builtin {
+    pub const ALL: &'static [Builtin] = &[Builtin::Help, Builtin::Me, Builtin::Shrug, Builtin::Poll];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Help => "help",
+            Builtin::Me => "me",
+            Builtin::Shrug => "shrug",
+            Builtin::Poll => "poll",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Builtin::Help => "list available commands",
+            Builtin::Me => "display an action, e.g. /me waves",
+            Builtin::Shrug => "append ¯\\_(ツ)_/¯ to your message",
+            Builtin::Poll => "start a poll, e.g. /poll \"lunch?\" \"pizza\" \"noodles\"",
+        }
+    }
+
+    /// run a builtin that does not need the registry, `/help` is handled by the registry itself
+    pub fn run(&self, args: &str, ctx: &CommandContext<'_>) -> Result<CommandOutput, AppError> {
+        match self {
+            Builtin::Help => Err(AppError::CommandError("/help must be run by the registry".to_string())),
+            Builtin::Me => {
+                if args.is_empty() {
+                    return Err(AppError::CommandError("usage: /me <action>".to_string()));
+                }
+                Ok(CommandOutput::Persisted {
+                    content: format!("_{} {}_", ctx.user.fullname, args),
+                })
+            }
+            Builtin::Shrug => {
+                let content = format!("{} ¯\\_(ツ)_/¯", args);
+                Ok(CommandOutput::Persisted {
+                    content: content.trim_start().to_string(),
+                })
+            }
+            Builtin::Poll => {
+                let parts = split_quoted(args);
+                if parts.len() < 3 {
+                    return Err(AppError::CommandError(
+                        "usage: /poll \"question\" \"option 1\" \"option 2\" ...".to_string(),
+                    ));
+                }
+                let mut lines = vec![format!("Poll: {}", parts[0])];
+                lines.extend(parts[1..].iter().enumerate().map(|(i, opt)| format!("{}. {}", i + 1, opt)));
+                Ok(CommandOutput::Persisted {
+                    content: lines.join("\n"),
+                })
+            }
+        }
+    }
+}
+
+/// split arguments on whitespace, keeping "double quoted" parts together
+fn split_quoted(args: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            '"' => {
+                if quoted && !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+                quoted = !quoted;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::User;
+
+    use super::*;
+
+    #[test]
+    fn split_quoted_should_work() {
+        assert_eq!(
+            split_quoted(r#""where to eat?" pizza "hot pot""#),
+            vec!["where to eat?", "pizza", "hot pot"]
+        );
+    }
+
+    #[test]
+    fn builtin_should_work() -> Result<()> {
+        let user = User::new(1, "vincent", "vincent@test.com");
+        let ctx = CommandContext {
+            user: &user,
+            chat_id: 1,
+        };
+
+        let ret = Builtin::Me.run("waves", &ctx)?;
+        assert_eq!(
+            ret,
+            CommandOutput::Persisted {
+                content: "_vincent waves_".to_string()
+            }
+        );
+
+        let ret = Builtin::Poll.run(r#""lunch?" pizza noodles"#, &ctx)?;
+        assert_eq!(
+            ret,
+            CommandOutput::Persisted {
+                content: "Poll: lunch?\n1. pizza\n2. noodles".to_string()
+            }
+        );
+
+        assert!(Builtin::Poll.run("lunch?", &ctx).is_err());
+        assert!(Builtin::Me.run("", &ctx).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{config::WebhookConfig, AppError};
+
+use super::{CommandContext, CommandOutput};
+
+#[derive(Debug)]
+pub(crate) struct WebhookCommand {
+    url: String,
+    description: String,
+}
+
+/// payload posted to the webhook
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookRequest {
+    pub command: String,
+    pub text: String,
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub user_name: String,
+}
+
+/// expected webhook response, the text is persisted unless `ephemeral` is set
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookResponse {
+    #[serde(default)]
+    pub ephemeral: bool,
+    pub text: String,
+}
+
+impl WebhookCommand {
+    pub fn new(config: &WebhookConfig) -> Self {
+        Self {
+            url: config.url.clone(),
+            description: config.description.clone(),
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub async fn call(
+        &self,
+        client: &reqwest::Client,
+        name: &str,
+        args: &str,
+        ctx: &CommandContext<'_>,
+    ) -> Result<CommandOutput, AppError> {
+        let req = WebhookRequest {
+            command: name.to_string(),
+            text: args.to_string(),
+            chat_id: ctx.chat_id,
+            user_id: ctx.user.id,
+            user_name: ctx.user.fullname.clone(),
+        };
+        let res: WebhookResponse = client
+            .post(&self.url)
+            .json(&req)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if res.ephemeral {
+            Ok(CommandOutput::Ephemeral { text: res.text })
+        } else {
+            Ok(CommandOutput::Persisted { content: res.text })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{routing::post, Json, Router};
+    use tokio::net::TcpListener;
+
+    use crate::{
+        commands::CommandRegistry,
+        config::{CommandConfig, WebhookConfig},
+        User,
+    };
+
+    use super::*;
+
+    async fn giphy(Json(req): Json<WebhookRequest>) -> Json<WebhookResponse> {
+        Json(WebhookResponse {
+            ephemeral: req.text.is_empty(),
+            text: format!("https://giphy.test/{}/{}", req.user_id, req.text),
+        })
+    }
+
+    #[tokio::test]
+    async fn webhook_command_should_work() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new().route("/giphy", post(giphy));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = CommandConfig {
+            webhooks: vec![WebhookConfig {
+                name: "giphy".to_string(),
+                url: format!("http://{}/giphy", addr),
+                description: "post a gif".to_string(),
+            }],
+        };
+        let registry = CommandRegistry::new(&config)?;
+        let user = User::new(1, "vincent", "vincent@test.com");
+        let ctx = CommandContext {
+            user: &user,
+            chat_id: 1,
+        };
+
+        let ret = registry.dispatch("giphy", "cat", &ctx).await?;
+        assert_eq!(
+            ret,
+            CommandOutput::Persisted {
+                content: "https://giphy.test/1/cat".to_string()
+            }
+        );
+
+        let ret = registry.dispatch("giphy", "", &ctx).await?;
+        assert!(matches!(ret, CommandOutput::Ephemeral { .. }));
+
+        Ok(())
+    }
+}
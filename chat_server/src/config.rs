@@ -1,4 +1,4 @@
-use std::fs::File;
+use std::{fs::File, path::PathBuf};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
@@ -7,6 +7,18 @@ use serde::{Deserialize, Serialize};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub commands: CommandConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub messages: MessageConfig,
+    #[serde(default)]
+    pub unfurl: UnfurlConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,6 +33,265 @@ pub struct ServerConfig {
     pub db_url: String,
 }
 
+/// slash commands served by external webhooks, e.g. /giphy
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CommandConfig {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageConfig {
+    /// seconds after sending during which the sender can still edit a message
+    #[serde(default = "default_edit_window")]
+    pub edit_window: u64,
+    /// seconds between two runs of the scheduler sending the due scheduled messages
+    #[serde(default = "default_schedule_interval")]
+    pub schedule_interval: u64,
+    /// seconds a user waits between two typing signals, in any of their chats
+    #[serde(default = "default_typing_interval")]
+    pub typing_interval: u64,
+    /// seconds after which clients stop showing a typing signal
+    #[serde(default = "default_typing_expiry")]
+    pub typing_expiry: u64,
+}
+
+/// link previews fetched for the urls in messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnfurlConfig {
+    #[serde(default = "default_unfurl_enabled")]
+    pub enabled: bool,
+    /// seconds for fetching a page, redirects included
+    #[serde(default = "default_unfurl_timeout")]
+    pub timeout: u64,
+    /// bytes read from a page at most, the metadata is in the head
+    #[serde(default = "default_unfurl_max_size")]
+    pub max_size: usize,
+    /// bytes of a preview image at most, the image is copied to the storage
+    #[serde(default = "default_unfurl_max_image_size")]
+    pub max_image_size: usize,
+    /// seconds a fetched preview is reused
+    #[serde(default = "default_unfurl_cache_ttl")]
+    pub cache_ttl: u64,
+    /// fetch from loopback and private networks, for local development only
+    #[serde(default)]
+    pub allow_private: bool,
+}
+
+/// emails sent to verify addresses
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// receives `{"to", "subject", "body"}` as json, mails are only logged if unset
+    #[serde(default)]
+    pub webhook: Option<String>,
+    /// page of the client that posts the token to /api/email/verify, the token is appended
+    #[serde(default = "default_verify_url")]
+    pub verify_url: String,
+}
+
+/// how long messages are kept, chats can set their own retention
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// days messages are kept in chats without their own retention, forever if unset
+    #[serde(default)]
+    pub default_days: Option<u32>,
+    /// seconds between two runs of the purge
+    #[serde(default = "default_purge_interval")]
+    pub purge_interval: u64,
+    /// messages deleted per transaction, small batches keep the locks short
+    #[serde(default = "default_purge_batch_size")]
+    pub batch_size: i64,
+    /// users who can put chats on legal hold and release them
+    #[serde(default)]
+    pub legal_hold_admins: Vec<i64>,
+}
+
+/// uploaded files are content addressed by sha256, stored under `base_dir` by the local backend
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    #[serde(default = "default_base_dir")]
+    pub base_dir: PathBuf,
+    /// max size of a single file in bytes
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: usize,
+    #[serde(default = "default_allowed_types")]
+    pub allowed_types: Vec<String>,
+    /// max width/height of the thumbnails generated for attached images
+    #[serde(default = "default_thumbnail_sizes")]
+    pub thumbnail_sizes: Vec<u32>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageBackend {
+    #[default]
+    Local,
+    S3(S3Config),
+}
+
+/// any S3 compatible service (AWS, MinIO, R2...), objects are addressed path style
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    /// e.g. https://s3.us-east-1.amazonaws.com or http://localhost:9000
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// lifetime of presigned urls in seconds
+    #[serde(default = "default_presign_expires")]
+    pub presign_expires: u64,
+    /// files larger than this are sent with multipart upload, S3 requires at least 5MB
+    #[serde(default = "default_part_size")]
+    pub part_size: usize,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            base_dir: default_base_dir(),
+            max_file_size: default_max_file_size(),
+            allowed_types: default_allowed_types(),
+            thumbnail_sizes: default_thumbnail_sizes(),
+        }
+    }
+}
+
+impl Default for MessageConfig {
+    fn default() -> Self {
+        Self {
+            edit_window: default_edit_window(),
+            schedule_interval: default_schedule_interval(),
+            typing_interval: default_typing_interval(),
+            typing_expiry: default_typing_expiry(),
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            webhook: None,
+            verify_url: default_verify_url(),
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            default_days: None,
+            purge_interval: default_purge_interval(),
+            batch_size: default_purge_batch_size(),
+            legal_hold_admins: vec![],
+        }
+    }
+}
+
+impl Default for UnfurlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_unfurl_enabled(),
+            timeout: default_unfurl_timeout(),
+            max_size: default_unfurl_max_size(),
+            max_image_size: default_unfurl_max_image_size(),
+            cache_ttl: default_unfurl_cache_ttl(),
+            allow_private: false,
+        }
+    }
+}
+
+fn default_purge_interval() -> u64 {
+    60 * 60
+}
+
+fn default_purge_batch_size() -> i64 {
+    500
+}
+
+fn default_unfurl_enabled() -> bool {
+    true
+}
+
+fn default_unfurl_timeout() -> u64 {
+    5
+}
+
+fn default_unfurl_max_size() -> usize {
+    512 * 1024
+}
+
+fn default_unfurl_max_image_size() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_unfurl_cache_ttl() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_edit_window() -> u64 {
+    15 * 60
+}
+
+fn default_schedule_interval() -> u64 {
+    10
+}
+
+fn default_verify_url() -> String {
+    "http://localhost:6688/verify-email?token=".to_string()
+}
+
+fn default_typing_interval() -> u64 {
+    3
+}
+
+fn default_typing_expiry() -> u64 {
+    6
+}
+
+fn default_base_dir() -> PathBuf {
+    PathBuf::from("/tmp/chat_server")
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_presign_expires() -> u64 {
+    15 * 60
+}
+
+fn default_part_size() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_max_file_size() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_allowed_types() -> Vec<String> {
+    ["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf", "text/plain"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn default_thumbnail_sizes() -> Vec<u32> {
+    vec![256, 1024]
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from /etc/config/app.yml or ./app.yml or from env CHAT_CONFIG
@@ -1,4 +1,4 @@
-use axum::{http::{header::InvalidHeaderValue, StatusCode}, response::{IntoResponse, Response}, Json};
+use axum::{extract::multipart::MultipartError, http::{header::InvalidHeaderValue, StatusCode}, response::{IntoResponse, Response}, Json};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -25,7 +25,88 @@ pub enum AppError {
     HttpHeaderError(#[from] InvalidHeaderValue),
 
     #[error("email already exists: {0}")]
-    EmailAlreadyExists(String)
+    EmailAlreadyExists(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("create chat error: {0}")]
+    CreateChatError(String),
+
+    #[error("create message error: {0}")]
+    CreateMessageError(String),
+
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("message is too long: {0} characters, at most {1}")]
+    MessageTooLong(usize, usize),
+
+    #[error("unfurl error: {0}")]
+    UnfurlError(String),
+
+    #[error("schedule message error: {0}")]
+    ScheduleError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
+
+    #[error("profile error: {0}")]
+    ProfileError(String),
+
+    #[error("retention error: {0}")]
+    RetentionError(String),
+
+    #[error("presence error: {0}")]
+    PresenceError(String),
+
+    #[error("mute error: {0}")]
+    MuteError(String),
+
+    #[error("too many requests: {0}")]
+    RateLimited(String),
+
+    #[error("user {0} is not a member of chat {1}")]
+    NotChatMember(i64, i64),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("command error: {0}")]
+    CommandError(String),
+
+    #[error("webhook error: {0}")]
+    WebhookError(#[from] reqwest::Error),
+
+    #[error("multipart error: {0}")]
+    MultipartError(#[from] MultipartError),
+
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("file too large: {0}")]
+    FileTooLarge(String),
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("chat file error: {0}")]
+    ChatFileError(String),
+
+    #[error("storage error: {0}")]
+    StorageError(String),
+
+    #[error("image error: {0}")]
+    ImageError(#[from] image::ImageError),
+
+    #[error("export error: {0}")]
+    ExportError(#[from] zip::result::ZipError),
+
+    #[error("import error: {0}")]
+    ImportError(String),
 }
 
 impl IntoResponse for AppError {
@@ -37,6 +118,33 @@ impl IntoResponse for AppError {
             AppError::InvalidCredentials(_) => StatusCode::UNAUTHORIZED,
             AppError::HttpHeaderError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             AppError::EmailAlreadyExists(_) => StatusCode::CONFLICT,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::CreateChatError(_) => StatusCode::BAD_REQUEST,
+            AppError::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::ReactionError(_) => StatusCode::BAD_REQUEST,
+            AppError::MessageTooLong(_, _) => StatusCode::BAD_REQUEST,
+            AppError::UnfurlError(_) => StatusCode::BAD_GATEWAY,
+            AppError::ScheduleError(_) => StatusCode::BAD_REQUEST,
+            AppError::SearchError(_) => StatusCode::BAD_REQUEST,
+            AppError::ProfileError(_) => StatusCode::BAD_REQUEST,
+            AppError::RetentionError(_) => StatusCode::BAD_REQUEST,
+            AppError::PresenceError(_) => StatusCode::BAD_REQUEST,
+            AppError::MuteError(_) => StatusCode::BAD_REQUEST,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::NotChatMember(_, _) => StatusCode::FORBIDDEN,
+            AppError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            AppError::CommandError(_) => StatusCode::BAD_REQUEST,
+            AppError::WebhookError(_) => StatusCode::BAD_GATEWAY,
+            AppError::MultipartError(_) => StatusCode::BAD_REQUEST,
+            AppError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::FileTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::ChatFileError(_) => StatusCode::BAD_REQUEST,
+            AppError::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::ImageError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ExportError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::ImportError(_) => StatusCode::BAD_REQUEST,
         };
 
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
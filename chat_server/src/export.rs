@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::File,
+    io::{Seek, Write},
+};
+
+use axum::body::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{stream, Stream, StreamExt as _};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::{io::AsyncReadExt as _, sync::mpsc};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{AppError, Chat, Message, MessageFormat, Profile, User};
+
+/// messages are read this many at a time
+const EXPORT_PAGE_SIZE: i64 = 500;
+/// the account archive is sent in chunks of this many bytes
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+/// entries read ahead of the zip writer
+const ARCHIVE_QUEUE_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ExportFormat {
+    /// one json object per message and line
+    #[default]
+    Jsonl,
+    Csv,
+    /// a static transcript, attachments stay links to the server and open only in a
+    /// signed in browser
+    Html,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct ExportChat {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+impl ExportFormat {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+/// a message as exported, with the name of the sender
+#[derive(Debug, Serialize)]
+struct ExportedMessage<'a> {
+    #[serde(flatten)]
+    message: &'a Message,
+    sender_name: &'a str,
+}
+
+const CSV_HEADER: &str =
+    "id,created_at,sender_id,sender_name,content,attachments,reply_to,thread_root_id,edited_at\r\n";
+
+const HTML_STYLE: &str = r#"body{font-family:system-ui,sans-serif;max-width:48rem;margin:2rem auto;padding:0 1rem;color:#222}
+ol{list-style:none;padding:0}
+li.message{padding:.5rem 0;border-bottom:1px solid #eee}
+li.reply{margin-left:2rem}
+.meta{color:#666;font-size:.85rem}
+.sender{font-weight:600;color:#222}
+.plain{white-space:pre-wrap}
+pre{background:#f6f6f6;padding:.5rem;overflow-x:auto}"#;
+
+/// the messages of the chat, thread replies included, oldest first. The body is
+/// produced a page at a time so large chats are never held in memory
+pub(crate) fn export_chat(
+    chat: Chat,
+    format: ExportFormat,
+    pool: PgPool,
+) -> impl Stream<Item = Result<Bytes, AppError>> {
+    let header = match format {
+        ExportFormat::Jsonl => String::new(),
+        ExportFormat::Csv => CSV_HEADER.to_string(),
+        ExportFormat::Html => html_header(&chat),
+    };
+    let footer = match format {
+        ExportFormat::Html => format!(
+            "</ol>\n<footer class=\"meta\">Exported {}. Attachments link to the server and open only when signed in.</footer>\n</body>\n</html>\n",
+            timestamp(Utc::now())
+        ),
+        _ => String::new(),
+    };
+
+    let chat_id = chat.id;
+    let pages = stream::try_unfold(Some(0), move |after_id| {
+        let pool = pool.clone();
+        async move {
+            let Some(after_id) = after_id else {
+                return Ok(None);
+            };
+            let messages = Message::list_after(chat_id, after_id, EXPORT_PAGE_SIZE, &pool).await?;
+            let Some(last) = messages.last() else {
+                return Ok(None);
+            };
+            let next = (messages.len() as i64 == EXPORT_PAGE_SIZE).then_some(last.id);
+            let senders = sender_names(&messages, &pool).await?;
+            let page = render_page(format, &messages, &senders)?;
+            Ok(Some((Bytes::from(page), next)))
+        }
+    });
+
+    stream::once(async move { Ok(Bytes::from(header)) })
+        .chain(pages)
+        .chain(stream::once(async move { Ok(Bytes::from(footer)) }))
+        .filter(|chunk| std::future::ready(!matches!(chunk, Ok(bytes) if bytes.is_empty())))
+}
+
+async fn sender_names(messages: &[Message], pool: &PgPool) -> Result<HashMap<i64, String>, AppError> {
+    let mut ids: Vec<i64> = messages.iter().map(|m| m.sender_id).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let users = User::fetch_by_ids(&ids, pool).await?;
+    Ok(users.into_iter().map(|u| (u.id, u.fullname)).collect())
+}
+
+fn render_page(format: ExportFormat, messages: &[Message], senders: &HashMap<i64, String>) -> Result<String, AppError> {
+    let mut page = String::new();
+    for message in messages {
+        let sender_name = senders.get(&message.sender_id).map(String::as_str).unwrap_or_default();
+        match format {
+            ExportFormat::Jsonl => {
+                let exported = ExportedMessage { message, sender_name };
+                page.push_str(&serde_json::to_string(&exported).map_err(std::io::Error::from)?);
+                page.push('\n');
+            }
+            ExportFormat::Csv => csv_row(&mut page, message, sender_name),
+            ExportFormat::Html => html_message(&mut page, message, sender_name),
+        }
+    }
+    Ok(page)
+}
+
+fn csv_row(out: &mut String, message: &Message, sender_name: &str) {
+    let optional = |id: Option<i64>| id.map(|id| id.to_string()).unwrap_or_default();
+    let fields = [
+        message.id.to_string(),
+        timestamp(message.created_at),
+        message.sender_id.to_string(),
+        sender_name.to_string(),
+        message.content.clone(),
+        message.images.join(" "),
+        optional(message.reply_to),
+        optional(message.thread_root_id),
+        message.edited_at.map(timestamp).unwrap_or_default(),
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        csv_field(out, field);
+    }
+    out.push_str("\r\n");
+}
+
+/// quote the field when it has a separator, a quote or a line break in it. Fields that
+/// spreadsheets would read as a formula are prefixed with `'`
+fn csv_field(out: &mut String, field: &str) {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\r', '\n']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(&field);
+    }
+}
+
+fn html_header(chat: &Chat) -> String {
+    let title = if chat.name.is_empty() {
+        format!("Chat {}", chat.id)
+    } else {
+        escape_html(&chat.name)
+    };
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n{HTML_STYLE}\n</style>\n</head>\n<body>\n<h1>{title}</h1>\n<ol>\n"
+    )
+}
+
+fn html_message(out: &mut String, message: &Message, sender_name: &str) {
+    let class = if message.thread_root_id.is_some() { "message reply" } else { "message" };
+    let _ = write!(
+        out,
+        "<li id=\"m{}\" class=\"{}\">\n<div class=\"meta\"><span class=\"sender\">{}</span> <time datetime=\"{}\">{}</time>",
+        message.id,
+        class,
+        escape_html(sender_name),
+        timestamp(message.created_at),
+        message.created_at.format("%Y-%m-%d %H:%M"),
+    );
+    if message.edited_at.is_some() {
+        out.push_str(" (edited)");
+    }
+    if let Some(root) = message.thread_root_id {
+        let _ = write!(out, " <a href=\"#m{root}\">in thread</a>");
+    }
+    out.push_str("</div>\n");
+    // markdown was sanitized when it was sent
+    match (message.format, &message.html) {
+        (MessageFormat::Markdown, Some(html)) => {
+            let _ = writeln!(out, "<div class=\"content\">{html}</div>");
+        }
+        _ => {
+            let _ = writeln!(out, "<div class=\"content plain\">{}</div>", escape_html(&message.content));
+        }
+    }
+    if !message.images.is_empty() {
+        out.push_str("<ul class=\"attachments\">\n");
+        for url in &message.images {
+            let _ = write!(out, "<li><a href=\"{0}\">{0}</a>", escape_html(url));
+            if let Some(meta) = message.image_meta.iter().find(|meta| &meta.url == url) {
+                let _ = write!(out, " ({}x{})", meta.width, meta.height);
+            }
+            out.push_str("</li>\n");
+        }
+        out.push_str("</ul>\n");
+    }
+    out.push_str("</li>\n");
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// what the account export hands to the blocking zip writer
+enum ArchiveEntry {
+    /// a whole json file
+    File { name: &'static str, data: Vec<u8> },
+    /// the next page of `messages.json`
+    Messages(Vec<Message>),
+}
+
+/// the data kept about a user as a zip of json files: the profile, the chats they are
+/// in, the messages they sent, their reactions, uploads and blocks. The data is read
+/// here while a blocking task compresses it into an anonymous temporary file, removed
+/// once the returned stream is dropped
+pub(crate) async fn export_account(
+    user_id: i64,
+    pool: &PgPool,
+) -> Result<impl Stream<Item = Result<Bytes, AppError>>, AppError> {
+    let (tx, rx) = mpsc::channel(ARCHIVE_QUEUE_SIZE);
+    let writer = tokio::task::spawn_blocking(move || write_archive(rx));
+    let read = read_account(user_id, pool, &tx).await;
+    drop(tx);
+    // a failing writer stops the reads too, its error is the one to report
+    let mut file = writer.await.map_err(std::io::Error::other)??;
+    read?;
+
+    file.rewind()?;
+    Ok(file_chunks(tokio::fs::File::from_std(file)))
+}
+
+async fn read_account(user_id: i64, pool: &PgPool, tx: &mpsc::Sender<ArchiveEntry>) -> Result<(), AppError> {
+    send_json(tx, "profile.json", &Profile::get(user_id, pool).await?).await?;
+    send_json(tx, "chats.json", &User::list_memberships(user_id, pool).await?).await?;
+    send_json(tx, "reactions.json", &User::list_reactions(user_id, pool).await?).await?;
+    send_json(tx, "files.json", &User::list_uploads(user_id, pool).await?).await?;
+    send_json(tx, "blocks.json", &User::list_blocks(user_id, pool).await?).await?;
+
+    let mut after_id = 0;
+    loop {
+        let messages = Message::list_by_sender(user_id, after_id, EXPORT_PAGE_SIZE, pool).await?;
+        let next = match messages.last() {
+            Some(last) if messages.len() as i64 == EXPORT_PAGE_SIZE => Some(last.id),
+            _ => None,
+        };
+        send(tx, ArchiveEntry::Messages(messages)).await?;
+        match next {
+            Some(id) => after_id = id,
+            None => return Ok(()),
+        }
+    }
+}
+
+async fn send_json<T: Serialize>(
+    tx: &mpsc::Sender<ArchiveEntry>,
+    name: &'static str,
+    value: &T,
+) -> Result<(), AppError> {
+    let data = serde_json::to_vec_pretty(value).map_err(std::io::Error::from)?;
+    send(tx, ArchiveEntry::File { name, data }).await
+}
+
+async fn send(tx: &mpsc::Sender<ArchiveEntry>, entry: ArchiveEntry) -> Result<(), AppError> {
+    tx.send(entry)
+        .await
+        .map_err(|_| std::io::Error::other("archive writer stopped").into())
+}
+
+/// runs on a blocking thread until the sender is dropped
+fn write_archive(mut rx: mpsc::Receiver<ArchiveEntry>) -> Result<File, AppError> {
+    let mut zip = ZipWriter::new(tempfile::tempfile()?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    // messages written so far, None until messages.json is started
+    let mut written: Option<usize> = None;
+    while let Some(entry) = rx.blocking_recv() {
+        match entry {
+            ArchiveEntry::File { name, data } => {
+                zip.start_file(name, options)?;
+                zip.write_all(&data)?;
+            }
+            ArchiveEntry::Messages(messages) => {
+                if written.is_none() {
+                    zip.start_file("messages.json", options)?;
+                    zip.write_all(b"[")?;
+                }
+                let count = written.get_or_insert(0);
+                for message in &messages {
+                    if *count > 0 {
+                        zip.write_all(b",\n")?;
+                    }
+                    *count += 1;
+                    serde_json::to_writer(&mut zip, message).map_err(std::io::Error::from)?;
+                }
+            }
+        }
+    }
+    if written.is_some() {
+        zip.write_all(b"]")?;
+    }
+    Ok(zip.finish()?)
+}
+
+fn file_chunks(file: tokio::fs::File) -> impl Stream<Item = Result<Bytes, AppError>> {
+    stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0; EXPORT_CHUNK_SIZE];
+        let len = file.read(&mut chunk).await?;
+        if len == 0 {
+            return Ok(None);
+        }
+        chunk.truncate(len);
+        Ok(Some((Bytes::from(chunk), file)))
+    })
+}
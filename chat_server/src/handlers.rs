@@ -1,16 +1,51 @@
+mod account;
 mod auth;
+mod blocks;
 mod chat;
+mod files;
 mod messages;
+mod pins;
+mod presence;
+mod profile;
+mod reactions;
+mod retention;
+mod scheduled;
+mod threads;
+mod typing;
+mod users;
 
+pub(crate) use account::*;
 pub(crate) use auth::*;
+pub(crate) use blocks::*;
 use axum_macros::debug_handler;
 pub(crate) use chat::*;
+pub(crate) use files::*;
 pub(crate) use messages::*;
+pub(crate) use pins::*;
+pub(crate) use presence::*;
+pub(crate) use profile::*;
+pub(crate) use reactions::*;
+pub(crate) use retention::*;
+pub(crate) use scheduled::*;
+pub(crate) use threads::*;
+pub(crate) use typing::*;
+pub(crate) use users::*;
 
 use tracing::instrument;
 
+use crate::{AppError, AppState, Chat, User};
+
 #[debug_handler]
 #[instrument]
 pub(crate) async fn index_handler() -> String {
     format!("Hello, world! {:?}", "test")
 }
+
+/// the chat if the user is a member of it
+pub(crate) async fn member_chat(id: i64, user: &User, state: &AppState) -> Result<Chat, AppError> {
+    match Chat::get_by_id(id, &state.pool).await? {
+        Some(chat) if chat.members.contains(&user.id) => Ok(chat),
+        Some(_) => Err(AppError::NotChatMember(user.id, id)),
+        None => Err(AppError::NotFound(format!("chat {}", id))),
+    }
+}
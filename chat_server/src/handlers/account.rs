@@ -0,0 +1,119 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use tracing::{instrument, warn};
+
+use crate::{
+    export::export_account,
+    models::DeleteAccount,
+    notify::{notify, ChatEvent},
+    AppError, AppState, User,
+};
+
+/// anonymize the account and drop its uploads, the messages stay under a placeholder
+/// name. Open sessions are closed and the tokens stop working
+#[debug_handler]
+#[instrument(skip(input))]
+pub(crate) async fn delete_account_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<DeleteAccount>,
+) -> Result<impl IntoResponse, AppError> {
+    let keys = User::delete_account(user.id, input, &state.pool).await?;
+    for key in keys {
+        if let Err(e) = state.storage.delete(&key).await {
+            warn!("delete file {} failed: {}", key, e);
+        }
+    }
+    notify(&[user.id], &ChatEvent::SessionsRevoked { user_id: user.id }, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn export_account_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let body = Body::from_stream(export_account(user.id, &state.pool).await?);
+    let disposition = format!("attachment; filename=\"export-{}.zip\"", user.id);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        AppConfig, Chat, Message,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn export_account_handler_should_zip_user_data() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let users = User::create_for_test(2, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        Message::create(CreateMessage::new("mine"), chat.id, users[0].id, &state.pool).await?;
+        Message::create(CreateMessage::new("theirs"), chat.id, users[1].id, &state.pool).await?;
+
+        let ret = export_account_handler(Extension(users[0].clone()), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.headers()[header::CONTENT_TYPE], "application/zip");
+        let body = ret.into_body().collect().await?.to_bytes();
+        let mut zip = zip::ZipArchive::new(Cursor::new(body))?;
+        let mut read = |name: &str| -> Result<serde_json::Value> {
+            let mut data = String::new();
+            zip.by_name(name)?.read_to_string(&mut data)?;
+            Ok(serde_json::from_str(&data)?)
+        };
+        assert_eq!(read("profile.json")?["email"], users[0].email);
+        assert_eq!(read("chats.json")?[0]["id"], chat.id);
+        let messages = read("messages.json")?;
+        assert_eq!(messages.as_array().map(Vec::len), Some(1));
+        assert_eq!(messages[0]["content"], "mine");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_account_handler_should_revoke_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let users = User::create_for_test(1, &state.pool).await?;
+
+        let input = DeleteAccount {
+            password: "wrong".to_string(),
+        };
+        let ret = delete_account_handler(Extension(users[0].clone()), State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        assert!(!User::is_deleted(users[0].id, &state.pool).await?);
+
+        let input = DeleteAccount {
+            password: "password".to_string(),
+        };
+        let ret = delete_account_handler(Extension(users[0].clone()), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        assert!(User::is_deleted(users[0].id, &state.pool).await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use tracing::instrument;
+
+use crate::{AppError, AppState, User};
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_blocks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let blocks = User::list_blocks(user.id, &state.pool).await?;
+    Ok(Json(blocks))
+}
+
+/// the blocked user is not told
+#[debug_handler]
+#[instrument]
+pub(crate) async fn block_user_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let block = User::block(user.id, id, &state.pool).await?;
+    Ok(Json(block))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn unblock_user_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    User::unblock(user.id, id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
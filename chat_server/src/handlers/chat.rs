@@ -1,22 +1,87 @@
-use axum::{response::IntoResponse, Extension};
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
 use axum_macros::debug_handler;
-use tracing::{info, instrument};
+use tracing::instrument;
 
-use crate::User;
+use crate::{
+    export::{export_chat, ExportChat},
+    models::{CreateChat, MarkRead, MuteChat},
+    notify::{notify, ChatEvent},
+    AppError, AppState, Chat, User,
+};
+
+use super::member_chat;
 
 #[debug_handler]
 #[instrument]
 pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
-) -> impl IntoResponse {
-    info!("user: {:?}", user);
-    "list_chat_handler"
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chats = Chat::fetch_summaries(user.id, &state.pool).await?;
+    Ok(Json(chats))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Chat::is_member(id, user.id, &state.pool).await? {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+
+    let read = Chat::mark_read(id, user.id, input, &state.pool).await?;
+    // keep the unread badges of the other devices of the user in sync
+    notify(&[user.id], &ChatEvent::ChatRead(read.clone()), &state.pool).await;
+    Ok(Json(read))
+}
+
+/// stop the new message events of the chat, mentions still come through
+#[debug_handler]
+#[instrument]
+pub(crate) async fn mute_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<MuteChat>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Chat::is_member(id, user.id, &state.pool).await? {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+
+    let mute = Chat::mute(id, user.id, input, &state.pool).await?;
+    Ok(Json(mute))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn unmute_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Chat::unmute(id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 #[debug_handler]
 #[instrument]
-pub(crate) async fn create_chat_handler() -> impl IntoResponse {
-    "create_chat_handler"
+pub(crate) async fn create_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateChat>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = Chat::create(input, user.id, &state.pool).await?;
+    Ok((StatusCode::CREATED, Json(chat)))
 }
 
 #[debug_handler]
@@ -31,3 +96,120 @@ pub(crate) async fn update_chat_handler(
 pub(crate) async fn delete_chat_handler() -> impl IntoResponse {
     "delete_chat_handler"
 }
+
+/// the whole history of the chat as json lines, csv or an html transcript, streamed
+/// as it is read
+#[debug_handler]
+#[instrument]
+pub(crate) async fn export_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(input): Query<ExportChat>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = member_chat(id, &user, &state).await?;
+    let format = input.format;
+    let disposition = format!("attachment; filename=\"chat-{}.{}\"", id, format.extension());
+    let body = Body::from_stream(export_chat(chat, format, state.pool.clone()));
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use crate::{export::ExportFormat, models::CreateMessage, AppConfig, ChatSummary, Message};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn mark_read_should_reset_unread_count() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        Message::create(CreateMessage::new("hello"), chat.id, users[0].id, &state.pool).await?;
+
+        let ret = list_chat_handler(Extension(users[1].clone()), State(state.clone()))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let chats: Vec<ChatSummary> = serde_json::from_slice(&body)?;
+        assert_eq!(chats[0].unread_count, 1);
+        assert_eq!(chats[0].last_message.as_ref().unwrap().content, "hello");
+
+        mark_read_handler(Extension(users[1].clone()), State(state.clone()), Path(chat.id), Json(MarkRead::default()))
+            .await?;
+        let chats = Chat::fetch_summaries(users[1].id, &state.pool).await?;
+        assert_eq!(chats[0].unread_count, 0);
+
+        let ret = mark_read_handler(Extension(users[2].clone()), State(state), Path(chat.id), Json(MarkRead::default()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_chat_handler_should_stream_history() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("launch", &[users[1].id], false), users[0].id, &state.pool).await?;
+        let root = Message::create(CreateMessage::new("hi, <all>"), chat.id, users[0].id, &state.pool).await?;
+        let input = CreateMessage {
+            reply_to: Some(root.id),
+            ..CreateMessage::new("say \"yes\"\nor no")
+        };
+        Message::create(input, chat.id, users[1].id, &state.pool).await?;
+        let input = CreateMessage::new("=HYPERLINK(\"http://evil.test\")");
+        Message::create(input, chat.id, users[1].id, &state.pool).await?;
+
+        let export = |format| {
+            let input = ExportChat { format };
+            export_chat_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Query(input))
+        };
+        let ret = export(ExportFormat::Jsonl).await?.into_response();
+        assert_eq!(ret.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+        let body = ret.into_body().collect().await?.to_bytes();
+        let lines: Vec<serde_json::Value> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["sender_name"], users[0].fullname);
+        assert_eq!(lines[1]["thread_root_id"], root.id);
+
+        let ret = export(ExportFormat::Csv).await?.into_response();
+        let body = String::from_utf8(ret.into_body().collect().await?.to_bytes().to_vec())?;
+        assert!(body.starts_with("id,created_at,sender_id,sender_name,content,"));
+        assert!(body.contains(",\"hi, <all>\","));
+        assert!(body.contains(",\"say \"\"yes\"\"\nor no\","));
+        // spreadsheets do not evaluate it
+        assert!(body.contains(",\"'=HYPERLINK(\"\"http://evil.test\"\")\","));
+
+        let ret = export(ExportFormat::Html).await?.into_response();
+        let body = String::from_utf8(ret.into_body().collect().await?.to_bytes().to_vec())?;
+        assert!(body.contains("<title>launch</title>"));
+        assert!(body.contains("hi, &lt;all&gt;"));
+        assert!(body.contains("class=\"message reply\""));
+        assert!(body.ends_with("</html>\n"));
+
+        // only members can export
+        let input = ExportChat::default();
+        let ret = export_chat_handler(Extension(users[2].clone()), State(state.clone()), Path(chat.id), Query(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
@@ -0,0 +1,412 @@
+use axum::{
+    extract::{Multipart, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect},
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+
+use crate::{
+    config::StorageConfig,
+    storage::{content_path, hash_path, validate_key, PresignedRequest},
+    AppError, AppState, ChatFile, User,
+};
+
+/// max number of files in a single upload request
+pub(crate) const MAX_UPLOAD_FILES: usize = 10;
+/// bytes of a presigned upload read back to find its content type
+const SNIFF_LEN: usize = 8192;
+
+/// ask for a presigned request to upload a file directly to the storage backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresignUpload {
+    pub filename: String,
+    pub content_type: String,
+    pub size: usize,
+    /// hex sha256 of the content
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresignedUpload {
+    /// url to reference the file in messages once uploaded and confirmed
+    pub url: String,
+    pub upload: PresignedRequest,
+}
+
+/// tell the server a presigned upload is done
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmUpload {
+    pub url: String,
+}
+
+#[debug_handler]
+#[instrument(skip(multipart))]
+pub(crate) async fn upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let config = &state.config.storage;
+    let mut urls = vec![];
+    while let Some(mut field) = multipart.next_field().await? {
+        let Some(filename) = field.file_name().map(|s| s.to_string()) else {
+            warn!("skip multipart field {:?} without filename", field.name());
+            continue;
+        };
+        if urls.len() >= MAX_UPLOAD_FILES {
+            return Err(AppError::ChatFileError(format!(
+                "at most {} files can be uploaded at once",
+                MAX_UPLOAD_FILES
+            )));
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            if data.len() + chunk.len() > config.max_file_size {
+                return Err(AppError::FileTooLarge(format!(
+                    "{} exceeds {} bytes",
+                    filename, config.max_file_size
+                )));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        // never trust the type the client declared
+        let sniffed = infer::get(&data);
+        let content_type = sniff_content_type(&data);
+        check_content_type(config, content_type)?;
+
+        let ext = sniffed
+            .map(|t| t.extension().to_string())
+            .or_else(|| file_ext(&filename))
+            .unwrap_or_default();
+        let path = content_path(&data, &ext);
+        let size = data.len() as i64;
+        state.storage.put(&path, data.into()).await?;
+        let file = ChatFile::create(&path, user.id, content_type, size, &state.pool).await?;
+        urls.push(file.url());
+    }
+
+    if urls.is_empty() {
+        return Err(AppError::ChatFileError("no file uploaded".to_string()));
+    }
+    Ok((StatusCode::CREATED, Json(urls)))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn presign_upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<PresignUpload>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = &state.config.storage;
+    if input.size > config.max_file_size {
+        return Err(AppError::FileTooLarge(format!(
+            "{} exceeds {} bytes",
+            input.filename, config.max_file_size
+        )));
+    }
+    check_content_type(config, &input.content_type)?;
+    let valid_hash = input.sha256.len() == 64
+        && input
+            .sha256
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+    if !valid_hash {
+        return Err(AppError::ChatFileError(
+            "sha256 must be a lowercase hex digest".to_string(),
+        ));
+    }
+
+    let ext = file_ext(&input.filename).unwrap_or_default();
+    let path = hash_path(&input.sha256, &ext);
+    let upload = state
+        .storage
+        .presign_put(&path, &input.sha256, input.size, &input.content_type)
+        .await?
+        .ok_or_else(|| {
+            AppError::ChatFileError("storage backend does not support presigned upload".to_string())
+        })?;
+    // knowing the hash of stored content must not be enough to claim it
+    if state.storage.head(&path).await?.is_some() {
+        return Err(AppError::ChatFileError(
+            "file already exists, upload it through /api/upload".to_string(),
+        ));
+    }
+    ChatFile::add_pending(&path, user.id, &state.pool).await?;
+
+    Ok(Json(PresignedUpload {
+        url: ChatFile::url_for(&path),
+        upload,
+    }))
+}
+
+/// record a presigned upload once its object is stored, the type is sniffed from the content
+#[debug_handler]
+#[instrument]
+pub(crate) async fn confirm_upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<ConfirmUpload>,
+) -> Result<impl IntoResponse, AppError> {
+    let config = &state.config.storage;
+    let path = ChatFile::path_from_url(&input.url)
+        .ok_or_else(|| AppError::ChatFileError(format!("invalid file url: {}", input.url)))?;
+    validate_key(path)?;
+    let not_found = || AppError::NotFound(format!("pending upload {}", input.url));
+
+    let size = state.storage.head(path).await?.ok_or_else(not_found)?;
+    if size > config.max_file_size as u64 {
+        return Err(AppError::FileTooLarge(format!(
+            "{} exceeds {} bytes",
+            input.url, config.max_file_size
+        )));
+    }
+    let prefix = state
+        .storage
+        .get_prefix(path, SNIFF_LEN)
+        .await?
+        .ok_or_else(not_found)?;
+    let content_type = sniff_content_type(&prefix);
+    check_content_type(config, content_type)?;
+
+    let file = ChatFile::confirm_pending(path, user.id, content_type, size as i64, &state.pool)
+        .await?
+        .ok_or_else(not_found)?;
+    Ok((StatusCode::CREATED, Json(file.url())))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn file_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    validate_key(&path)?;
+    if !ChatFile::can_access(&path, user.id, &state.pool).await? {
+        return Err(AppError::PermissionDenied(format!(
+            "user {} can not access file {}",
+            user.id, path
+        )));
+    }
+    let not_found = || AppError::NotFound(format!("file {}", path));
+    let file = ChatFile::find_by_path(&path, &state.pool)
+        .await?
+        .ok_or_else(not_found)?;
+
+    // let the client download from the backend directly when it can
+    if let Some(url) = state.storage.presign_get(&path, &file.content_type).await? {
+        return Ok(Redirect::temporary(&url).into_response());
+    }
+    let data = state.storage.get(&path).await?.ok_or_else(not_found)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, file.content_type),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+/// content type from the magic bytes, utf-8 content without any is plain text.
+/// `data` may be a prefix of the content
+pub(crate) fn sniff_content_type(data: &[u8]) -> &'static str {
+    if let Some(t) = infer::get(data) {
+        return t.mime_type();
+    }
+    match std::str::from_utf8(data) {
+        Ok(_) => "text/plain",
+        // a prefix may end in the middle of a character
+        Err(e) if e.error_len().is_none() => "text/plain",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+fn check_content_type(config: &StorageConfig, content_type: &str) -> Result<(), AppError> {
+    if config.allowed_types.iter().any(|t| t == content_type) {
+        Ok(())
+    } else {
+        Err(AppError::UnsupportedMediaType(content_type.to_string()))
+    }
+}
+
+/// lowercase alphanumeric extension of the filename, if any
+fn file_ext(filename: &str) -> Option<String> {
+    let (_, ext) = filename.rsplit_once('.')?;
+    let valid = !ext.is_empty() && ext.len() <= 10 && ext.chars().all(|c| c.is_ascii_alphanumeric());
+    valid.then(|| ext.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{body::Body, extract::Request, routing::post, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        AppConfig, Chat, Message,
+    };
+
+    use super::*;
+
+    const BOUNDARY: &str = "chat-boundary";
+
+    fn multipart_body(filename: &str, content_type: &str, data: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    fn upload_request(user: &User, body: Vec<u8>) -> Result<Request> {
+        let mut req = Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))?;
+        req.extensions_mut().insert(user.clone());
+        Ok(req)
+    }
+
+    #[test]
+    fn sniff_content_type_should_ignore_declared_type() {
+        assert_eq!(sniff_content_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff_content_type("héllo".as_bytes()), "text/plain");
+        assert_eq!(sniff_content_type(&[0xff, 0xfe, 0x00, 0x81]), "application/octet-stream");
+        assert_eq!(sniff_content_type(&"héllo".as_bytes()[..2]), "text/plain");
+    }
+
+    #[test]
+    fn file_ext_should_work() {
+        assert_eq!(file_ext("a.TXT"), Some("txt".to_string()));
+        assert_eq!(file_ext("noext"), None);
+        assert_eq!(file_ext("a.t/x"), None);
+    }
+
+    #[tokio::test]
+    async fn upload_and_download_should_work() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        let dir = tempfile::tempdir()?;
+        config.storage.base_dir = dir.path().to_path_buf();
+        config.storage.max_file_size = 16;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let app = Router::new()
+            .route("/upload", post(upload_handler))
+            .with_state(state.clone());
+
+        let body = multipart_body("hello.txt", "text/plain", b"hello");
+        let res = app.clone().oneshot(upload_request(&users[0], body)?).await?;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let urls: Vec<String> = serde_json::from_slice(&res.into_body().collect().await?.to_bytes())?;
+        assert_eq!(urls.len(), 1);
+        let path = ChatFile::path_from_url(&urls[0]).unwrap().to_string();
+
+        let body = multipart_body("big.txt", "text/plain", &[b'a'; 17]);
+        let res = app.clone().oneshot(upload_request(&users[0], body)?).await?;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body = multipart_body("photo.png", "image/png", &[0xff, 0xfe, 0x00, 0x81]);
+        let res = app.clone().oneshot(upload_request(&users[0], body)?).await?;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let body = multipart_body("run.sh", "application/x-sh", b"#!/bin/sh");
+        let res = app.oneshot(upload_request(&users[0], body)?).await?;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        // uploader can read it, others only after it is sent to a shared chat
+        let res = file_handler(Extension(users[0].clone()), State(state.clone()), Path(path.clone()))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(res.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(&res.into_body().collect().await?.to_bytes()[..], b"hello");
+
+        let res = file_handler(Extension(users[1].clone()), State(state.clone()), Path(path.clone()))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            images: urls,
+            ..Default::default()
+        };
+        Message::create(input, chat.id, users[0].id, &state.pool).await?;
+
+        let res = file_handler(Extension(users[1].clone()), State(state.clone()), Path(path.clone()))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = file_handler(Extension(users[2].clone()), State(state.clone()), Path(path))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // local storage can not presign
+        let input = PresignUpload {
+            filename: "hello.txt".to_string(),
+            content_type: "text/plain".to_string(),
+            size: 5,
+            sha256: "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_string(),
+        };
+        let res = presign_upload_handler(Extension(users[0].clone()), State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn confirm_upload_should_need_pending_upload() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        let dir = tempfile::tempdir()?;
+        config.storage.base_dir = dir.path().to_path_buf();
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(2, &state.pool).await?;
+        let path = content_path(b"hello", "txt");
+        let url = ChatFile::url_for(&path);
+        let confirm = |user: &User| {
+            confirm_upload_handler(
+                Extension(user.clone()),
+                State(state.clone()),
+                Json(ConfirmUpload { url: url.clone() }),
+            )
+        };
+
+        // not uploaded yet
+        ChatFile::add_pending(&path, users[0].id, &state.pool).await?;
+        assert_eq!(confirm(&users[0]).await.into_response().status(), StatusCode::NOT_FOUND);
+        assert!(ChatFile::find_by_path(&path, &state.pool).await?.is_none());
+
+        // stored, but only the user who asked for the upload can claim it
+        state.storage.put(&path, "hello".into()).await?;
+        assert_eq!(confirm(&users[1]).await.into_response().status(), StatusCode::NOT_FOUND);
+        assert_eq!(confirm(&users[0]).await.into_response().status(), StatusCode::CREATED);
+        let file = ChatFile::find_uploaded(&path, users[0].id, &state.pool).await?.unwrap();
+        assert_eq!((file.content_type.as_str(), file.size), ("text/plain", 5));
+        assert!(!ChatFile::can_access(&path, users[1].id, &state.pool).await?);
+
+        // the pending upload is used up
+        assert_eq!(confirm(&users[0]).await.into_response().status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
@@ -1,15 +1,494 @@
-use axum::response::IntoResponse;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
 use axum_macros::debug_handler;
-use tracing::instrument;
+use tracing::{instrument, warn};
+
+use crate::{
+    commands::{self, CommandContext, CommandOutput, EphemeralMessage},
+    images, mentions,
+    models::{CreateMessage, ForwardMessage, ListMessages, SearchMessages, UpdateMessage},
+    notify::{notify, notify_message, ChatEvent},
+    AppError, AppState, Chat, ChatFile, Message, User,
+};
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn send_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(mut input): Json<CreateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let Some(chat) = Chat::get_by_id(id, &state.pool).await? else {
+        return Err(AppError::NotFound(format!("chat {}", id)));
+    };
+    if !chat.members.contains(&user.id) {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+
+    // slash commands are interpreted here instead of being stored as plain text
+    let command = commands::parse(&input.content).filter(|(name, _)| state.commands.contains(name));
+    if let Some((name, args)) = command {
+        let ctx = CommandContext {
+            user: &user,
+            chat_id: id,
+        };
+        match state.commands.dispatch(name, args, &ctx).await? {
+            CommandOutput::Ephemeral { text } => {
+                return Ok((StatusCode::OK, Json(EphemeralMessage::new(id, text))).into_response());
+            }
+            CommandOutput::Persisted { content } => input.content = content,
+        }
+    } else {
+        commands::unescape(&mut input.content);
+    }
+
+    input.images = images::prepare_images(&state, user.id, input.images).await?;
+    let mut message = Message::create(input, id, user.id, &state.pool).await?;
+    publish_message(&state, &chat, &mut message).await?;
+    Ok((StatusCode::CREATED, Json(message)).into_response())
+}
+
+/// resolve the mentions of a new message and notify the members, the followers of its
+/// thread and the mentioned users
+pub(crate) async fn publish_message(state: &AppState, chat: &Chat, message: &mut Message) -> Result<(), AppError> {
+    let mentioned = mentions::update_mentions(state, chat, message).await?;
+    match message.thread_root_id {
+        Some(root_id) => {
+            let mut followers = Message::thread_followers(root_id, &state.pool).await?;
+            followers.retain(|id| chat.members.contains(id));
+            notify_message(&followers, message, ChatEvent::ThreadReply, &state.pool).await?;
+        }
+        None => notify_message(&chat.members, message, ChatEvent::NewMessage, &state.pool).await?,
+    }
+    notify_mentions(state, message, mentioned).await?;
+    state.unfurler.enqueue(message);
+    Ok(())
+}
+
+/// users who blocked the sender are not told they were mentioned
+async fn notify_mentions(state: &AppState, message: &Message, mut mentioned: Vec<i64>) -> Result<(), AppError> {
+    if mentioned.is_empty() {
+        return Ok(());
+    }
+    let blockers = User::blocked_by(message.sender_id, &mentioned, &state.pool).await?;
+    mentioned.retain(|id| !blockers.contains(id));
+    notify(&mentioned, &ChatEvent::Mention(message.clone()), &state.pool).await;
+    Ok(())
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn forward_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+    Json(input): Json<ForwardMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Chat::is_member(id, user.id, &state.pool).await? {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+    let Some(target) = Chat::get_by_id(input.chat_id, &state.pool).await? else {
+        return Err(AppError::NotFound(format!("chat {}", input.chat_id)));
+    };
+    if !target.members.contains(&user.id) {
+        return Err(AppError::NotChatMember(user.id, target.id));
+    }
+
+    let mut message = Message::forward(mid, id, input, user.id, &state.pool).await?;
+    publish_message(&state, &target, &mut message).await?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Chat::is_member(id, user.id, &state.pool).await? {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+
+    let mut messages = Message::list(input, id, &state.pool).await?;
+    Message::flag_blocked(&mut messages, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
+
+/// messages mentioning the current user, newest first
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_mentions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut messages = Message::list_mentions(input, user.id, &state.pool).await?;
+    Message::flag_blocked(&mut messages, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
 
 #[debug_handler]
 #[instrument]
-pub(crate) async fn send_msg_handler() -> impl IntoResponse {
-    "send_msg_handler"
+pub(crate) async fn search_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut results = Message::search(input, user.id, &state.pool).await?;
+    Message::flag_blocked(results.iter_mut().map(|r| &mut r.message), user.id, &state.pool).await?;
+    Ok(Json(results))
 }
 
 #[debug_handler]
 #[instrument]
-pub(crate) async fn list_msg_handler() -> impl IntoResponse {
-    "list_msg_handler"
+pub(crate) async fn update_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let Some(chat) = Chat::get_by_id(id, &state.pool).await? else {
+        return Err(AppError::NotFound(format!("chat {}", id)));
+    };
+    if !chat.members.contains(&user.id) {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+
+    let edit_window = state.config.messages.edit_window;
+    let mut message = Message::update(input, mid, id, user.id, edit_window, &state.pool).await?;
+    let mentioned = mentions::update_mentions(&state, &chat, &mut message).await?;
+    notify_message(&chat.members, &message, ChatEvent::MessageUpdated, &state.pool).await?;
+    notify_mentions(&state, &message, mentioned).await?;
+    state.unfurler.enqueue(&message);
+    Ok(Json(message))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn delete_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let Some(chat) = Chat::get_by_id(id, &state.pool).await? else {
+        return Err(AppError::NotFound(format!("chat {}", id)));
+    };
+    if !chat.members.contains(&user.id) {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+
+    let (message, images) = Message::delete(mid, &chat, user.id, &state.pool).await?;
+    // the message is gone either way, leftovers in the storage are only wasted space
+    for key in ChatFile::purge(&images, &state.pool).await? {
+        if let Err(e) = state.storage.delete(&key).await {
+            warn!("delete file {} failed: {}", key, e);
+        }
+    }
+    notify_message(&chat.members, &message, ChatEvent::MessageDeleted, &state.pool).await?;
+    Ok(Json(message))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_msg_revisions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Chat::is_member(id, user.id, &state.pool).await? {
+        return Err(AppError::NotChatMember(user.id, id));
+    }
+    if Message::get_by_id(mid, id, &state.pool).await?.is_none() {
+        return Err(AppError::NotFound(format!("message {} in chat {}", mid, id)));
+    }
+
+    let revisions = Message::revisions(mid, &state.pool).await?;
+    Ok(Json(revisions))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::CreateChat, AppConfig, MessageRevision};
+
+    use super::*;
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn send_msg_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+
+        // plain text is stored as is
+        let input = CreateMessage::new("hello");
+        let ret = send_msg_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+
+        // persisted command output replaces the content
+        let input = CreateMessage::new("/shrug fine");
+        let ret = send_msg_handler(Extension(users[1].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg: Message = serde_json::from_slice(&body)?;
+        assert_eq!(msg.content, "fine ¯\\_(ツ)_/¯");
+
+        // ephemeral output is not stored
+        let input = CreateMessage::new("/help");
+        let ret = send_msg_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg: EphemeralMessage = serde_json::from_slice(&body)?;
+        assert!(msg.ephemeral);
+
+        // escaped slash is stored as text
+        let input = CreateMessage::new("//help");
+        send_msg_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input)).await?;
+
+        // unknown commands are plain text
+        let input = CreateMessage::new("/usr/bin is on the path");
+        let ret = send_msg_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+
+        let messages = Message::list(ListMessages::default(), chat.id, &state.pool).await?;
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0].content, "/usr/bin is on the path");
+        assert_eq!(messages[1].content, "/help");
+
+        // non member
+        let input = CreateMessage::new("hi");
+        let ret = send_msg_handler(Extension(users[2].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        // a known command with bad arguments
+        let input = CreateMessage::new("/me");
+        let ret = send_msg_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+
+        // too long is a validation error, not an oversized body
+        let input = CreateMessage::new(&"a".repeat(4001));
+        let ret = send_msg_handler(Extension(users[0].clone()), State(state), Path(chat.id), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mentions_should_be_recorded() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id, users[2].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+
+        let input = CreateMessage::new("hey @user1");
+        let ret = send_msg_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg: Message = serde_json::from_slice(&body)?;
+        assert_eq!(msg.mentions.len(), 1);
+        assert_eq!(msg.mentions[0].user_id, Some(users[1].id));
+        assert_eq!(&msg.content[msg.mentions[0].start..msg.mentions[0].end], "@user1");
+
+        let input = CreateMessage::new("@channel standup");
+        send_msg_handler(Extension(users[1].clone()), State(state.clone()), Path(chat.id), Json(input)).await?;
+
+        let ret = list_mentions_handler(Extension(users[1].clone()), State(state.clone()), Query(ListMessages::default()))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let messages: Vec<Message> = serde_json::from_slice(&body)?;
+        // @channel does not mention the sender
+        assert_eq!(messages.len(), 1);
+        let messages = Message::list_mentions(ListMessages::default(), users[2].id, &state.pool).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "@channel standup");
+
+        // editing the mention away removes it
+        let path = Path((chat.id, msg.id));
+        update_msg_handler(Extension(users[0].clone()), State(state.clone()), path, Json(UpdateMessage::new("hey")))
+            .await?;
+        let messages = Message::list_mentions(ListMessages::default(), users[1].id, &state.pool).await?;
+        assert!(messages.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn message_events_should_be_flagged_per_member() -> Result<()> {
+        use sqlx::postgres::PgListener;
+
+        use crate::{
+            models::MuteChat,
+            notify::{recv_event, NOTIFY_CHANNEL},
+        };
+
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id, users[2].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+        Chat::mute(chat.id, users[1].id, MuteChat::default(), &state.pool).await?;
+        User::block(users[2].id, users[0].id, &state.pool).await?;
+
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+        let input = CreateMessage::new("@channel lunch?");
+        send_msg_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input)).await?;
+
+        let payload = recv_event(&mut listener, &state.pool).await?;
+        assert_eq!(payload["event"]["type"], "new_message");
+        assert_eq!(payload["members"], serde_json::json!([users[0].id]));
+        assert_eq!(payload["event"].get("muted"), None);
+        assert_eq!(payload["event"].get("sender_blocked"), None);
+        let payload = recv_event(&mut listener, &state.pool).await?;
+        assert_eq!(payload["members"], serde_json::json!([users[2].id]));
+        assert_eq!(payload["event"]["sender_blocked"], true);
+        // muted members still get the message, flagged
+        let payload = recv_event(&mut listener, &state.pool).await?;
+        assert_eq!(payload["event"]["type"], "new_message");
+        assert_eq!(payload["members"], serde_json::json!([users[1].id]));
+        assert_eq!(payload["event"]["muted"], true);
+        // the user who blocked the sender is not mentioned
+        let payload = recv_event(&mut listener, &state.pool).await?;
+        assert_eq!(payload["event"]["type"], "mention");
+        assert_eq!(payload["members"], serde_json::json!([users[1].id]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_msg_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+        let message = Message::create(CreateMessage::new("helo"), chat.id, users[0].id, &state.pool).await?;
+
+        let path = Path((chat.id, message.id));
+        let input = UpdateMessage::new("hello");
+        let ret = update_msg_handler(Extension(users[0].clone()), State(state.clone()), path, Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg: Message = serde_json::from_slice(&body)?;
+        assert_eq!(msg.content, "hello");
+
+        // other members can not edit
+        let input = UpdateMessage::new("hi");
+        let ret = update_msg_handler(Extension(users[1].clone()), State(state.clone()), Path((chat.id, message.id)), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let ret = list_msg_revisions_handler(Extension(users[1].clone()), State(state.clone()), Path((chat.id, message.id)))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let revisions: Vec<MessageRevision> = serde_json::from_slice(&body)?;
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].content, "helo");
+
+        let ret = list_msg_revisions_handler(Extension(users[2].clone()), State(state), Path((chat.id, message.id)))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_msg_should_purge_files() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        let dir = tempfile::tempdir()?;
+        config.storage.base_dir = dir.path().to_path_buf();
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(2, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+
+        let path = "2cf/24d/ba5f.txt";
+        state.storage.put(path, "hello".into()).await?;
+        let file = ChatFile::create(path, users[0].id, "text/plain", 5, &state.pool).await?;
+        let input = CreateMessage {
+            content: "see attached".to_string(),
+            images: vec![file.url()],
+            ..Default::default()
+        };
+        let message = Message::create(input, chat.id, users[0].id, &state.pool).await?;
+
+        let ret = delete_msg_handler(Extension(users[1].clone()), State(state.clone()), Path((chat.id, message.id)))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let ret = delete_msg_handler(Extension(users[0].clone()), State(state.clone()), Path((chat.id, message.id)))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg: Message = serde_json::from_slice(&body)?;
+        assert!(msg.deleted_at.is_some());
+        assert!(msg.images.is_empty());
+        assert_eq!(state.storage.get(path).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forward_msg_should_check_both_chats() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let source = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        let target = Chat::create(CreateChat::new("", &[users[2].id], false), users[1].id, &state.pool).await?;
+        let message = Message::create(CreateMessage::new("ship it"), source.id, users[0].id, &state.pool).await?;
+
+        // users[0] is not in the target chat
+        let input = ForwardMessage {
+            chat_id: target.id,
+            ..Default::default()
+        };
+        let ret = forward_msg_handler(Extension(users[0].clone()), State(state.clone()), Path((source.id, message.id)), Json(input.clone()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let ret = forward_msg_handler(Extension(users[1].clone()), State(state.clone()), Path((source.id, message.id)), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let msg: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(msg["chat_id"], target.id);
+        assert_eq!(msg["quote"]["sender_id"], users[0].id);
+        assert_eq!(msg["quote"]["content"], "ship it");
+
+        Ok(())
+    }
 }
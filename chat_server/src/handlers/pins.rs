@@ -0,0 +1,121 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use tracing::instrument;
+
+use super::member_chat;
+use crate::{
+    notify::{notify, ChatEvent},
+    AppError, AppState, Message, User,
+};
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_pins_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    member_chat(id, &user, &state).await?;
+    let mut pins = Message::list_pins(id, &state.pool).await?;
+    Message::flag_blocked(pins.iter_mut().map(|p| &mut p.message), user.id, &state.pool).await?;
+    Ok(Json(pins))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn pin_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = member_chat(id, &user, &state).await?;
+    let pin = Message::pin(mid, &chat, user.id, &state.pool).await?;
+    let event = ChatEvent::MessagePinned {
+        chat_id: id,
+        pin: pin.clone(),
+    };
+    notify(&chat.members, &event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(pin)))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn unpin_msg_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = member_chat(id, &user, &state).await?;
+    if Message::unpin(mid, &chat, user.id, &state.pool).await? {
+        let event = ChatEvent::MessageUnpinned {
+            chat_id: id,
+            message_id: mid,
+            user_id: user.id,
+        };
+        notify(&chat.members, &event, &state.pool).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        AppConfig, Chat, PinnedMessage,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn pin_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+        let message = Message::create(CreateMessage::new("hi"), chat.id, users[0].id, &state.pool).await?;
+
+        let ret = pin_msg_handler(Extension(users[1].clone()), State(state.clone()), Path((chat.id, message.id)))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+
+        let ret = pin_msg_handler(Extension(users[2].clone()), State(state.clone()), Path((chat.id, message.id)))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let ret = list_pins_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let pins: Vec<PinnedMessage> = serde_json::from_slice(&body)?;
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins[0].message.id, message.id);
+        assert!(!pins[0].message.sender_blocked);
+
+        User::block(users[1].id, users[0].id, &state.pool).await?;
+        let ret = list_pins_handler(Extension(users[1].clone()), State(state.clone()), Path(chat.id))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let pins: Vec<PinnedMessage> = serde_json::from_slice(&body)?;
+        assert!(pins[0].message.sender_blocked);
+
+        let ret = unpin_msg_handler(Extension(users[0].clone()), State(state.clone()), Path((chat.id, message.id)))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        assert!(Message::list_pins(chat.id, &state.pool).await?.is_empty());
+
+        Ok(())
+    }
+}
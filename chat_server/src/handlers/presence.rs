@@ -0,0 +1,142 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    models::{SetStatus, MAX_PRESENCE_IDS},
+    notify::{notify, ChatEvent},
+    AppError, AppState, Presence, User,
+};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PresenceQuery {
+    /// comma separated user ids
+    ids: String,
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(query): Query<PresenceQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let ids = parse_ids(&query.ids)?;
+    let presences = Presence::fetch(&ids, user.id, &state.pool).await?;
+    Ok(Json(presences))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn set_status_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<SetStatus>,
+) -> Result<impl IntoResponse, AppError> {
+    let presence = Presence::set_status(user.id, input, &state.pool).await?;
+    publish_presence(&presence, &state).await?;
+    Ok(Json(presence))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn clear_status_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let presence = Presence::clear_status(user.id, &state.pool).await?;
+    publish_presence(&presence, &state).await?;
+    Ok(Json(presence))
+}
+
+async fn publish_presence(presence: &Presence, state: &AppState) -> Result<(), AppError> {
+    let watchers = Presence::watchers(presence.user_id, &state.pool).await?;
+    let event = ChatEvent::PresenceChanged(presence.clone());
+    notify(&watchers, &event, &state.pool).await;
+    Ok(())
+}
+
+fn parse_ids(ids: &str) -> Result<Vec<i64>, AppError> {
+    let mut ret = Vec::new();
+    for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        let id = id
+            .parse()
+            .map_err(|_| AppError::PresenceError(format!("invalid user id: {}", id)))?;
+        if !ret.contains(&id) {
+            ret.push(id);
+        }
+    }
+    if ret.len() > MAX_PRESENCE_IDS {
+        return Err(AppError::PresenceError(format!(
+            "at most {} users at once",
+            MAX_PRESENCE_IDS
+        )));
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::http::StatusCode;
+    use http_body_util::BodyExt;
+
+    use crate::{models::CreateChat, AppConfig, Chat, PresenceStatus, UserStatus};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn presence_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id], false);
+        Chat::create(input, users[0].id, &state.pool).await?;
+
+        let input = SetStatus {
+            status: Some(UserStatus::Away),
+            text: Some("lunch".to_string()),
+            expires_at: None,
+        };
+        set_status_handler(Extension(users[0].clone()), State(state.clone()), Json(input)).await?;
+
+        let query = PresenceQuery {
+            ids: format!("{}, {},{}", users[0].id, users[1].id, users[0].id),
+        };
+        let ret = presence_handler(Extension(users[1].clone()), State(state.clone()), Query(query))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let presences: Vec<Presence> = serde_json::from_slice(&body)?;
+        assert_eq!(presences.len(), 2);
+        assert_eq!(presences[0].status, PresenceStatus::Offline);
+        assert_eq!(presences[0].status_text.as_deref(), Some("lunch"));
+
+        // strangers only see themselves
+        let query = PresenceQuery {
+            ids: format!("{},{}", users[0].id, users[2].id),
+        };
+        let ret = presence_handler(Extension(users[2].clone()), State(state.clone()), Query(query))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let presences: Vec<Presence> = serde_json::from_slice(&body)?;
+        assert_eq!(presences.len(), 1);
+        assert_eq!(presences[0].user_id, users[2].id);
+
+        clear_status_handler(Extension(users[0].clone()), State(state.clone())).await?;
+        assert_eq!(Presence::fetch(&[users[0].id], users[0].id, &state.pool).await?[0].status_text, None);
+
+        let query = PresenceQuery { ids: "1,x".to_string() };
+        let ret = presence_handler(Extension(users[1].clone()), State(state.clone()), Query(query))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+}
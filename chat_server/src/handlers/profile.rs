@@ -0,0 +1,152 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use axum_macros::debug_handler;
+use tracing::{instrument, warn};
+
+use crate::{
+    images::prepare_avatar,
+    mail::Mail,
+    models::{ChangeEmail, ChangePassword, UpdateProfile, VerifyEmail},
+    AppError, AppState, ChatFile, Profile, User,
+};
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn get_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = Profile::get(user.id, &state.pool).await?;
+    Ok(Json(profile))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn update_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(mut input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let avatar = match input.avatar.take() {
+        Some(url) if url.is_empty() => Some(None),
+        Some(url) => Some(Some(prepare_avatar(&state, user.id, &url).await?)),
+        None => None,
+    };
+    let (profile, replaced) = Profile::update(user.id, input, avatar, &state.pool).await?;
+    // the profile is updated either way, leftovers in the storage are only wasted space
+    if let Some(url) = replaced {
+        for key in ChatFile::purge(&[url], &state.pool).await? {
+            if let Err(e) = state.storage.delete(&key).await {
+                warn!("delete file {} failed: {}", key, e);
+            }
+        }
+    }
+    Ok(Json(profile))
+}
+
+#[debug_handler]
+#[instrument(skip(input))]
+pub(crate) async fn change_password_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<ChangePassword>,
+) -> Result<impl IntoResponse, AppError> {
+    User::change_password(user.id, input, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// send a verification link to the new address, the email changes once it is followed
+#[debug_handler]
+#[instrument(skip(input))]
+pub(crate) async fn change_email_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<ChangeEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    let to = input.email.trim().to_string();
+    let token = User::request_email_change(user.id, input, &state.pool).await?;
+    let mail = Mail {
+        to,
+        subject: "Verify your new email address".to_string(),
+        body: format!(
+            "Hi {},\n\nopen this link to use this address for your account:\n{}{}\n\nIt expires in 24 hours.",
+            user.fullname, state.config.mail.verify_url, token
+        ),
+    };
+    state.mailer.send(&mail).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[debug_handler]
+#[instrument(skip(input))]
+pub(crate) async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = User::verify_email_change(input, &state.pool).await?;
+    Ok(Json(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use crate::{storage::content_path, AppConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn update_profile_handler_should_set_avatar() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        let dir = tempfile::tempdir()?;
+        config.storage.base_dir = dir.path().to_path_buf();
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(2, &state.pool).await?;
+
+        let img = image::RgbImage::from_pixel(300, 200, image::Rgb([200, 10, 10]));
+        let mut data = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut data, image::ImageFormat::Png)?;
+        let data = data.into_inner();
+        let path = content_path(&data, "png");
+        state.storage.put(&path, data.clone().into()).await?;
+        ChatFile::create(&path, users[0].id, "image/png", data.len() as i64, &state.pool).await?;
+
+        // only the uploader can use it
+        let input = UpdateProfile {
+            avatar: Some(ChatFile::url_for(&path)),
+            ..Default::default()
+        };
+        let ret = update_profile_handler(Extension(users[1].clone()), State(state.clone()), Json(input.clone()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+
+        let ret = update_profile_handler(Extension(users[0].clone()), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let profile: Profile = serde_json::from_slice(&body)?;
+        let avatar = profile.avatar.expect("avatar should be set").0;
+        assert_eq!(avatar.thumbnails.len(), 3);
+        let avatar_path = ChatFile::path_from_url(&avatar.url).expect("should be a file url");
+        // everyone can see it
+        assert!(ChatFile::can_access(avatar_path, users[1].id, &state.pool).await?);
+
+        let input = UpdateProfile {
+            avatar: Some("".to_string()),
+            ..Default::default()
+        };
+        update_profile_handler(Extension(users[0].clone()), State(state.clone()), Json(input)).await?;
+        assert_eq!(Profile::get(users[0].id, &state.pool).await?.avatar, None);
+        // the old avatar and its thumbnails are purged
+        assert!(ChatFile::find_by_path(avatar_path, &state.pool).await?.is_none());
+        assert!(state.storage.get(avatar_path).await?.is_none());
+        for thumbnail in avatar.thumbnails {
+            let path = ChatFile::path_from_url(&thumbnail.url).expect("should be a file url");
+            assert!(state.storage.get(path).await?.is_none());
+        }
+        // the uploaded source stays
+        assert!(state.storage.get(&path).await?.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::member_chat;
+use crate::{
+    notify::{notify, ChatEvent},
+    AppError, AppState, Message, User,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddReaction {
+    pub emoji: String,
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+    Json(input): Json<AddReaction>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = member_chat(id, &user, &state).await?;
+    let (reaction, created) = Message::add_reaction(mid, id, user.id, &input.emoji, &state.pool).await?;
+    // reacting twice changes nothing, the members already got the event
+    if !created {
+        return Ok((StatusCode::OK, Json(reaction)));
+    }
+    let event = ChatEvent::ReactionAdded {
+        chat_id: id,
+        reaction: reaction.clone(),
+    };
+    notify(&chat.members, &event, &state.pool).await;
+    Ok((StatusCode::CREATED, Json(reaction)))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid, emoji)): Path<(i64, i64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = member_chat(id, &user, &state).await?;
+    if Message::remove_reaction(mid, id, user.id, &emoji, &state.pool).await? {
+        let event = ChatEvent::ReactionRemoved {
+            chat_id: id,
+            message_id: mid,
+            user_id: user.id,
+            emoji,
+        };
+        notify(&chat.members, &event, &state.pool).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        AppConfig, Chat,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reaction_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+        let message = Message::create(CreateMessage::new("hi"), chat.id, users[0].id, &state.pool).await?;
+
+        let input = AddReaction {
+            emoji: "👍".to_string(),
+        };
+        let ret = add_reaction_handler(Extension(users[1].clone()), State(state.clone()), Path((chat.id, message.id)), Json(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let ret = add_reaction_handler(Extension(users[1].clone()), State(state.clone()), Path((chat.id, message.id)), Json(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let ret = add_reaction_handler(Extension(users[2].clone()), State(state.clone()), Path((chat.id, message.id)), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let path = Path((chat.id, message.id, "👍".to_string()));
+        let ret = remove_reaction_handler(Extension(users[1].clone()), State(state.clone()), path)
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        let message = Message::get_by_id(message.id, chat.id, &state.pool).await?.unwrap();
+        assert!(message.reactions.is_empty());
+
+        Ok(())
+    }
+}
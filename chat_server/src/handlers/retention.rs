@@ -0,0 +1,116 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use tracing::instrument;
+
+use crate::{models::SetRetention, AppError, AppState, Chat, ChatRetention, User};
+
+use super::member_chat;
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn get_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    member_chat(id, &user, &state).await?;
+    let retention = Chat::retention(id, state.config.retention.default_days, &state.pool).await?;
+    Ok(Json(retention))
+}
+
+/// chat admins decide how long the messages are kept, single chats follow the default
+#[debug_handler]
+#[instrument]
+pub(crate) async fn set_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<SetRetention>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = member_chat(id, &user, &state).await?;
+    if !chat.is_admin(user.id) {
+        return Err(AppError::PermissionDenied(format!(
+            "user {} can not change the retention of chat {}",
+            user.id, id
+        )));
+    }
+    let retention = Chat::set_retention(id, input, state.config.retention.default_days, &state.pool).await?;
+    Ok(Json(retention))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn hold_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    set_legal_hold(id, true, &user, &state).await
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn release_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    set_legal_hold(id, false, &user, &state).await
+}
+
+/// only the configured legal hold admins, members or not, can hold and release chats
+async fn set_legal_hold(id: i64, hold: bool, user: &User, state: &AppState) -> Result<Json<ChatRetention>, AppError> {
+    let config = &state.config.retention;
+    if !config.legal_hold_admins.contains(&user.id) {
+        return Err(AppError::PermissionDenied(format!(
+            "user {} can not change the legal hold of chat {}",
+            user.id, id
+        )));
+    }
+    let retention = Chat::set_legal_hold(id, hold, user.id, config.default_days, &state.pool).await?;
+    Ok(Json(retention))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::http::StatusCode;
+
+    use crate::{models::CreateChat, AppConfig};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn legal_hold_should_be_for_admins_only() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        config.retention.legal_hold_admins = vec![3];
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("legal", &[users[1].id], false), users[0].id, &state.pool).await?;
+
+        // the chat admin sets the retention but can not hold the chat
+        let input = SetRetention { days: Some(90) };
+        let ret = set_retention_handler(Extension(users[1].clone()), State(state.clone()), Path(chat.id), Json(input.clone()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        set_retention_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input)).await?;
+        let ret = hold_chat_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        assert_eq!(users[2].id, 3);
+        hold_chat_handler(Extension(users[2].clone()), State(state.clone()), Path(chat.id)).await?;
+        let retention = Chat::retention(chat.id, None, &state.pool).await?;
+        assert!(retention.legal_hold);
+        assert_eq!(retention.effective_days, None);
+        release_chat_handler(Extension(users[2].clone()), State(state.clone()), Path(chat.id)).await?;
+        assert_eq!(Chat::retention(chat.id, None, &state.pool).await?.effective_days, Some(90));
+        Ok(())
+    }
+}
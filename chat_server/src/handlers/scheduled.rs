@@ -0,0 +1,127 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use tracing::instrument;
+
+use super::member_chat;
+use crate::{
+    commands, images,
+    models::{CreateScheduledMessage, ListScheduledMessages, UpdateScheduledMessage},
+    AppError, AppState, ScheduledMessage, User,
+};
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn create_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(mut input): Json<CreateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    member_chat(id, &user, &state).await?;
+    // commands answer the caller right away, there is nobody to answer later
+    if let Some((name, _)) = commands::parse(&input.content) {
+        return Err(AppError::ScheduleError(format!("command /{} can not be scheduled", name)));
+    }
+    commands::unescape(&mut input.content);
+
+    input.images = images::prepare_images(&state, user.id, input.images).await?;
+    let scheduled = ScheduledMessage::create(input, id, user.id, &state.pool).await?;
+    Ok((StatusCode::CREATED, Json(scheduled)))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListScheduledMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = ScheduledMessage::list(input, user.id, &state.pool).await?;
+    Ok(Json(scheduled))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn update_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some((name, _)) = input.content.as_deref().and_then(commands::parse) {
+        return Err(AppError::ScheduleError(format!("command /{} can not be scheduled", name)));
+    }
+    let mut input = input;
+    if let Some(content) = input.content.as_mut() {
+        commands::unescape(content);
+    }
+    let scheduled = ScheduledMessage::update(id, input, user.id, &state.pool).await?;
+    Ok(Json(scheduled))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn cancel_scheduled_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    ScheduledMessage::cancel(id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chrono::{Duration, Utc};
+
+    use crate::{models::CreateChat, AppConfig, Chat};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn scheduled_handlers_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        let input = CreateScheduledMessage {
+            content: "standup in 5".to_string(),
+            send_at: Utc::now() + Duration::hours(1),
+            ..Default::default()
+        };
+
+        let ret = create_scheduled_handler(Extension(users[2].clone()), State(state.clone()), Path(chat.id), Json(input.clone()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let command = CreateScheduledMessage {
+            content: "/shrug".to_string(),
+            ..input.clone()
+        };
+        let ret = create_scheduled_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(command))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+
+        let ret = create_scheduled_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+
+        let scheduled = ScheduledMessage::list(Default::default(), users[0].id, &state.pool).await?;
+        assert_eq!(scheduled.len(), 1);
+        let ret = cancel_scheduled_handler(Extension(users[0].clone()), State(state.clone()), Path(scheduled[0].id))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use tracing::instrument;
+
+use crate::{models::ListMessages, AppError, AppState, Chat, Message, User};
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let root_id = thread_root(id, mid, &user, &state).await?;
+    let mut messages = Message::list_thread(input, root_id, id, &state.pool).await?;
+    Message::flag_blocked(&mut messages, user.id, &state.pool).await?;
+    Ok(Json(messages))
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn follow_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let root_id = thread_root(id, mid, &user, &state).await?;
+    Message::follow_thread(root_id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn unfollow_thread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let root_id = thread_root(id, mid, &user, &state).await?;
+    Message::unfollow_thread(root_id, user.id, &state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// the root of the thread the message belongs to, any message of a thread can be used
+async fn thread_root(chat_id: i64, id: i64, user: &User, state: &AppState) -> Result<i64, AppError> {
+    if !Chat::is_member(chat_id, user.id, &state.pool).await? {
+        return Err(AppError::NotChatMember(user.id, chat_id));
+    }
+    let message = Message::get_by_id(id, chat_id, &state.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("message {} in chat {}", id, chat_id)))?;
+    Ok(message.thread_root_id.unwrap_or(message.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        AppConfig,
+    };
+
+    use super::*;
+
+    fn reply(content: &str, reply_to: i64) -> CreateMessage {
+        CreateMessage {
+            reply_to: Some(reply_to),
+            ..CreateMessage::new(content)
+        }
+    }
+
+    #[tokio::test]
+    async fn thread_should_work() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(4, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id, users[2].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+        let pool = &state.pool;
+
+        let root = Message::create(CreateMessage::new("lunch?"), chat.id, users[0].id, pool).await?;
+        let first = Message::create(reply("sure", root.id), chat.id, users[1].id, pool).await?;
+        assert_eq!(first.thread_root_id, Some(root.id));
+        // replying to a reply stays in the same thread
+        let second = Message::create(reply("me too", first.id), chat.id, users[2].id, pool).await?;
+        assert_eq!(second.reply_to, Some(first.id));
+        assert_eq!(second.thread_root_id, Some(root.id));
+
+        let root = Message::get_by_id(root.id, chat.id, pool).await?.unwrap();
+        assert_eq!(root.reply_count, 2);
+        assert_eq!(root.last_reply_at, Some(second.created_at));
+        // replies are not in the chat history
+        let messages = Message::list(ListMessages::default(), chat.id, pool).await?;
+        assert_eq!(messages.len(), 1);
+
+        let ret = list_thread_handler(
+            Extension(users[0].clone()),
+            State(state.clone()),
+            Path((chat.id, first.id)),
+            Query(ListMessages::default()),
+        )
+        .await?
+        .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let replies: Vec<Message> = serde_json::from_slice(&body)?;
+        let ids: Vec<_> = replies.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [second.id, first.id]);
+
+        // root author and repliers follow, others can opt in and out
+        let followers = Message::thread_followers(root.id, pool).await?;
+        assert_eq!(followers, [users[0].id, users[1].id, users[2].id]);
+        unfollow_thread_handler(Extension(users[1].clone()), State(state.clone()), Path((chat.id, root.id))).await?;
+        let followers = Message::thread_followers(root.id, pool).await?;
+        assert_eq!(followers, [users[0].id, users[2].id]);
+
+        let ret = follow_thread_handler(Extension(users[3].clone()), State(state.clone()), Path((chat.id, root.id)))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let ret = Message::create(reply("?", 10086), chat.id, users[0].id, pool).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension,
+};
+use axum_macros::debug_handler;
+use chrono::{Duration, Utc};
+use tracing::instrument;
+
+use super::member_chat;
+use crate::{
+    notify::{notify_inline, ChatEvent},
+    AppError, AppState, User,
+};
+
+/// tell the other members the user is typing, nothing is stored
+#[debug_handler]
+#[instrument]
+pub(crate) async fn typing_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = member_chat(id, &user, &state).await?;
+    if !state.typing.allow(user.id) {
+        return Err(AppError::RateLimited(format!("user {} is already typing", user.id)));
+    }
+
+    let others: Vec<i64> = chat.members.into_iter().filter(|m| *m != user.id).collect();
+    let ttl = Duration::seconds(state.config.messages.typing_expiry as i64);
+    let event = ChatEvent::Typing {
+        chat_id: id,
+        user_id: user.id,
+        expires_at: Utc::now() + ttl,
+    };
+    notify_inline(&others, &event, &state.pool).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use sqlx::postgres::PgListener;
+
+    use crate::{models::CreateChat, notify::NOTIFY_CHANNEL, AppConfig, Chat};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn typing_handler_should_be_rate_limited() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let input = CreateChat::new("", &[users[1].id], false);
+        let chat = Chat::create(input, users[0].id, &state.pool).await?;
+        let input = CreateChat::new("", &[users[2].id], false);
+        let other = Chat::create(input, users[0].id, &state.pool).await?;
+
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+        let ret = typing_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        // carried by the notification, nothing is stored
+        let payload: serde_json::Value = serde_json::from_str(listener.recv().await?.payload())?;
+        assert_eq!(payload["members"], serde_json::json!([users[1].id]));
+        assert_eq!(payload["event"]["type"], "typing");
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM chat_events").fetch_one(&state.pool).await?;
+        assert_eq!(stored, 0);
+
+        let ret = typing_handler(Extension(users[0].clone()), State(state.clone()), Path(chat.id))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        // nor in another chat
+        let ret = typing_handler(Extension(users[0].clone()), State(state.clone()), Path(other.id))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let ret = typing_handler(Extension(users[2].clone()), State(state.clone()), Path(chat.id))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use tracing::instrument;
+
+use crate::{models::ListUsers, AppError, AppState, DirectoryUser, User};
+
+#[debug_handler]
+#[instrument]
+pub(crate) async fn list_users_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListUsers>,
+) -> Result<impl IntoResponse, AppError> {
+    let users = DirectoryUser::search(input, user.id, &state.pool).await?;
+    Ok(Json(users))
+}
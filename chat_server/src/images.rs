@@ -0,0 +1,435 @@
+use std::io::Cursor;
+
+use axum::body::Bytes;
+use image::{metadata::Orientation, DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader, Limits};
+use img_parts::{DynImage, ImageEXIF};
+
+use crate::{
+    storage::{content_path, thumbnail_path},
+    AppError, AppState, Avatar, ChatFile, ImageMeta, Thumbnail,
+};
+
+const MAX_IMAGE_DIMENSION: u32 = 16384;
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+/// blurhash only needs a tiny version of the image
+const BLURHASH_SIZE: u32 = 32;
+/// side of the square avatar, larger images are scaled down
+const AVATAR_SIZE: u32 = 512;
+const AVATAR_THUMBNAIL_SIZES: [u32; 3] = [48, 96, 192];
+
+/// result of processing an image, before anything is stored
+#[derive(Debug)]
+struct ProcessedImage {
+    /// the original without EXIF, None if it had none
+    stripped: Option<StrippedImage>,
+    width: u32,
+    height: u32,
+    blurhash: String,
+    thumbnails: Vec<EncodedThumbnail>,
+}
+
+#[derive(Debug)]
+struct StrippedImage {
+    data: Bytes,
+    /// extension and content type of the copy when the EXIF orientation had to be applied
+    /// by re-encoding, None if it is the original without the EXIF block
+    reencoded: Option<(&'static str, &'static str)>,
+}
+
+#[derive(Debug)]
+struct EncodedThumbnail {
+    size: u32,
+    width: u32,
+    height: u32,
+    ext: &'static str,
+    content_type: &'static str,
+    data: Vec<u8>,
+}
+
+/// prepare the images attached to a message: strip EXIF (it carries the GPS location),
+/// generate thumbnails and record width/height/blurhash. Returns the urls to store in the
+/// message, which point to the stripped copy when the original had EXIF.
+pub(crate) async fn prepare_images(
+    state: &AppState,
+    user_id: i64,
+    urls: Vec<String>,
+) -> Result<Vec<String>, AppError> {
+    // nothing is read or copied from files the sender can not read
+    let paths: Vec<&str> = urls.iter().filter_map(|url| ChatFile::path_from_url(url)).collect();
+    if let Some(path) = ChatFile::inaccessible(&paths, user_id, &state.pool).await?.first() {
+        return Err(AppError::CreateMessageError(format!(
+            "file {} does not exist",
+            ChatFile::url_for(path)
+        )));
+    }
+
+    let mut prepared = Vec::with_capacity(urls.len());
+    for url in urls {
+        let Some(path) = ChatFile::path_from_url(&url) else {
+            prepared.push(url);
+            continue;
+        };
+        let file = match ChatFile::find_by_path(path, &state.pool).await? {
+            Some(file) if is_image(&file.content_type) => file,
+            // not an image, or unknown and rejected when the message is created
+            _ => {
+                prepared.push(url);
+                continue;
+            }
+        };
+        if ImageMeta::find_by_path(&file.path, &state.pool).await?.is_some() {
+            prepared.push(url);
+            continue;
+        }
+        let path = prepare_image(state, user_id, file).await?;
+        prepared.push(ChatFile::url_for(&path));
+    }
+    Ok(prepared)
+}
+
+/// process a single image, returns the path of the image to use
+async fn prepare_image(state: &AppState, user_id: i64, file: ChatFile) -> Result<String, AppError> {
+    let data = state
+        .storage
+        .get(&file.path)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("file {}", file.path)))?;
+    let sizes = state.config.storage.thumbnail_sizes.clone();
+    let processed = tokio::task::spawn_blocking(move || process_image(data, &sizes))
+        .await
+        .map_err(|e| AppError::ChatFileError(format!("process image failed: {}", e)))??;
+
+    let mut path = file.path;
+    if let Some(stripped) = processed.stripped {
+        let (ext, content_type) = match stripped.reencoded {
+            Some((ext, content_type)) => (ext, content_type),
+            None => (
+                path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default(),
+                file.content_type.as_str(),
+            ),
+        };
+        let stripped_path = content_path(&stripped.data, ext);
+        let size = stripped.data.len() as i64;
+        state.storage.put(&stripped_path, stripped.data).await?;
+        ChatFile::create(&stripped_path, user_id, content_type, size, &state.pool).await?;
+        path = stripped_path;
+        if ImageMeta::find_by_path(&path, &state.pool).await?.is_some() {
+            return Ok(path);
+        }
+    }
+
+    let mut thumbnails = Vec::with_capacity(processed.thumbnails.len());
+    for thumb in processed.thumbnails {
+        let thumb_path = thumbnail_path(&path, thumb.size, thumb.ext);
+        let size = thumb.data.len() as i64;
+        state.storage.put(&thumb_path, thumb.data.into()).await?;
+        ChatFile::create(&thumb_path, user_id, thumb.content_type, size, &state.pool).await?;
+        thumbnails.push(Thumbnail {
+            size: thumb.size,
+            width: thumb.width,
+            height: thumb.height,
+            url: ChatFile::url_for(&thumb_path),
+        });
+    }
+    ImageMeta::create(
+        &path,
+        processed.width,
+        processed.height,
+        &processed.blurhash,
+        thumbnails,
+        &state.pool,
+    )
+    .await?;
+
+    Ok(path)
+}
+
+/// crop the image the user uploaded at `url` to a centered square and store it with its
+/// smaller variants. Re-encoding drops the EXIF
+pub(crate) async fn prepare_avatar(state: &AppState, user_id: i64, url: &str) -> Result<Avatar, AppError> {
+    let invalid = || AppError::ProfileError(format!("avatar must be an image uploaded by the user: {}", url));
+    let path = ChatFile::path_from_url(url).ok_or_else(invalid)?;
+    let file = ChatFile::find_uploaded(path, user_id, &state.pool)
+        .await?
+        .filter(|file| is_image(&file.content_type))
+        .ok_or_else(invalid)?;
+    let data = state
+        .storage
+        .get(&file.path)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("file {}", file.path)))?;
+    let mut encoded = tokio::task::spawn_blocking(move || process_avatar(data))
+        .await
+        .map_err(|e| AppError::ChatFileError(format!("process image failed: {}", e)))??
+        .into_iter();
+
+    let Some(avatar) = encoded.next() else {
+        return Err(invalid());
+    };
+    let path = content_path(&avatar.data, avatar.ext);
+    let size = avatar.data.len() as i64;
+    state.storage.put(&path, avatar.data.into()).await?;
+    ChatFile::create(&path, user_id, avatar.content_type, size, &state.pool).await?;
+
+    let mut thumbnails = Vec::with_capacity(AVATAR_THUMBNAIL_SIZES.len());
+    for thumb in encoded {
+        let thumb_path = thumbnail_path(&path, thumb.size, thumb.ext);
+        let size = thumb.data.len() as i64;
+        state.storage.put(&thumb_path, thumb.data.into()).await?;
+        ChatFile::create(&thumb_path, user_id, thumb.content_type, size, &state.pool).await?;
+        thumbnails.push(Thumbnail {
+            size: thumb.size,
+            width: thumb.width,
+            height: thumb.height,
+            url: ChatFile::url_for(&thumb_path),
+        });
+    }
+    Ok(Avatar {
+        url: ChatFile::url_for(&path),
+        thumbnails,
+    })
+}
+
+fn is_image(content_type: &str) -> bool {
+    matches!(content_type, "image/png" | "image/jpeg" | "image/gif" | "image/webp")
+}
+
+fn process_image(data: Bytes, sizes: &[u32]) -> Result<ProcessedImage, AppError> {
+    let (img, format, rotated) = decode(&data)?;
+    let (width, height) = img.dimensions();
+    // dropping the EXIF drops the orientation too, so rotated photos keep it in the pixels
+    let stripped = if rotated {
+        let encoded = encode(&img, width.max(height), format)?;
+        Some(StrippedImage {
+            data: encoded.data.into(),
+            reencoded: Some((encoded.ext, encoded.content_type)),
+        })
+    } else {
+        strip_exif(&data).map(|data| StrippedImage { data, reencoded: None })
+    };
+
+    let small = img.thumbnail(BLURHASH_SIZE, BLURHASH_SIZE).to_rgba8();
+    let (x, y) = BLURHASH_COMPONENTS;
+    let blurhash = blurhash::encode(x, y, small.width(), small.height(), small.as_raw())
+        .map_err(|e| AppError::ChatFileError(format!("blurhash failed: {:?}", e)))?;
+
+    let mut thumbnails = vec![];
+    for &size in sizes {
+        if size >= width.max(height) {
+            continue;
+        }
+        thumbnails.push(encode(&img.thumbnail(size, size), size, format)?);
+    }
+
+    Ok(ProcessedImage {
+        stripped,
+        width,
+        height,
+        blurhash,
+        thumbnails,
+    })
+}
+
+/// the square avatar first, then the variants smaller than it
+fn process_avatar(data: Bytes) -> Result<Vec<EncodedThumbnail>, AppError> {
+    let (img, format, _) = decode(&data)?;
+    let (width, height) = img.dimensions();
+    let side = width.min(height);
+    let square = img.crop_imm((width - side) / 2, (height - side) / 2, side, side);
+    let side = side.min(AVATAR_SIZE);
+
+    let mut encoded = vec![encode(&square.thumbnail(side, side), side, format)?];
+    for size in AVATAR_THUMBNAIL_SIZES {
+        if size < side {
+            encoded.push(encode(&square.thumbnail(size, size), size, format)?);
+        }
+    }
+    Ok(encoded)
+}
+
+/// decode the image upright, the flag tells whether its EXIF orientation was applied
+fn decode(data: &Bytes) -> Result<(DynamicImage, Option<ImageFormat>, bool), AppError> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let format = reader.format();
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    // a broken EXIF block is no reason to reject the image
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok((img, format, orientation != Orientation::NoTransforms))
+}
+
+/// photos stay jpeg, anything that may have transparency becomes png
+fn encode(img: &DynamicImage, size: u32, format: Option<ImageFormat>) -> Result<EncodedThumbnail, AppError> {
+    let mut buf = Cursor::new(Vec::new());
+    let (ext, content_type) = match format {
+        Some(ImageFormat::Jpeg) => {
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_to(&mut buf, ImageFormat::Jpeg)?;
+            ("jpg", "image/jpeg")
+        }
+        _ => {
+            img.write_to(&mut buf, ImageFormat::Png)?;
+            ("png", "image/png")
+        }
+    };
+    Ok(EncodedThumbnail {
+        size,
+        width: img.width(),
+        height: img.height(),
+        ext,
+        content_type,
+        data: buf.into_inner(),
+    })
+}
+
+/// drop the EXIF block losslessly, None if there is none
+fn strip_exif(data: &Bytes) -> Option<Bytes> {
+    let mut img = DynImage::from_bytes(data.clone()).ok()??;
+    img.exif()?;
+    img.set_exif(None);
+    Some(img.encoder().bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use image::{Rgb, RgbImage};
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        AppConfig, Chat, Message, User,
+    };
+
+    use super::*;
+
+    fn test_image(format: ImageFormat) -> Result<Vec<u8>> {
+        let img = RgbImage::from_fn(600, 400, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 128]));
+        let mut buf = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(img).write_to(&mut buf, format)?;
+        Ok(buf.into_inner())
+    }
+
+    /// a jpeg with an APP1 EXIF segment right after SOI
+    fn jpeg_with_exif() -> Result<Vec<u8>> {
+        jpeg_with_exif_block(b"Exif\0\0II*\0\x08\0\0\0\0\0")
+    }
+
+    /// a jpeg whose EXIF says to rotate it 90 degrees clockwise
+    fn rotated_jpeg() -> Result<Vec<u8>> {
+        jpeg_with_exif_block(
+            b"Exif\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0",
+        )
+    }
+
+    fn jpeg_with_exif_block(exif: &[u8]) -> Result<Vec<u8>> {
+        let jpeg = test_image(ImageFormat::Jpeg)?;
+        let len = (exif.len() + 2) as u16;
+        let mut data = jpeg[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&len.to_be_bytes());
+        data.extend_from_slice(exif);
+        data.extend_from_slice(&jpeg[2..]);
+        Ok(data)
+    }
+
+    #[test]
+    fn process_image_should_work() -> Result<()> {
+        let data = test_image(ImageFormat::Png)?;
+        let processed = process_image(data.into(), &[256, 1024])?;
+        assert!(processed.stripped.is_none());
+        assert_eq!((processed.width, processed.height), (600, 400));
+        assert!(!processed.blurhash.is_empty());
+        assert_eq!(processed.thumbnails.len(), 1);
+        let thumb = &processed.thumbnails[0];
+        assert_eq!((thumb.width, thumb.height, thumb.ext), (256, 171, "png"));
+        Ok(())
+    }
+
+    #[test]
+    fn process_image_should_apply_orientation() -> Result<()> {
+        let processed = process_image(rotated_jpeg()?.into(), &[256])?;
+        assert_eq!((processed.width, processed.height), (400, 600));
+        let stripped = processed.stripped.expect("exif should be stripped");
+        assert_eq!(stripped.reencoded, Some(("jpg", "image/jpeg")));
+        assert!(!stripped.data.windows(4).any(|w| w == b"Exif"));
+        assert_eq!(image::load_from_memory(&stripped.data)?.dimensions(), (400, 600));
+        let thumb = &processed.thumbnails[0];
+        assert_eq!((thumb.width, thumb.height), (171, 256));
+        Ok(())
+    }
+
+    #[test]
+    fn process_avatar_should_crop_to_square() -> Result<()> {
+        let data = test_image(ImageFormat::Jpeg)?;
+        let encoded = process_avatar(data.into())?;
+        let sizes: Vec<_> = encoded.iter().map(|e| (e.size, e.width, e.height, e.ext)).collect();
+        assert_eq!(
+            sizes,
+            [(400, 400, 400, "jpg"), (48, 48, 48, "jpg"), (96, 96, 96, "jpg"), (192, 192, 192, "jpg")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn strip_exif_should_work() -> Result<()> {
+        let data = Bytes::from(jpeg_with_exif()?);
+        let stripped = strip_exif(&data).expect("exif should be stripped");
+        assert!(stripped.len() < data.len());
+        assert!(!stripped.windows(4).any(|w| w == b"Exif"));
+        assert!(strip_exif(&stripped).is_none());
+        // still a valid image
+        image::load_from_memory(&stripped)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prepare_images_should_work() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        let dir = tempfile::tempdir()?;
+        config.storage.base_dir = dir.path().to_path_buf();
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(2, &state.pool).await?;
+
+        let data = jpeg_with_exif()?;
+        let path = content_path(&data, "jpg");
+        state.storage.put(&path, data.clone().into()).await?;
+        ChatFile::create(&path, users[0].id, "image/jpeg", data.len() as i64, &state.pool).await?;
+
+        let urls = prepare_images(&state, users[0].id, vec![ChatFile::url_for(&path)]).await?;
+        assert_ne!(urls[0], ChatFile::url_for(&path));
+
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        let input = CreateMessage {
+            content: "photo".to_string(),
+            images: urls.clone(),
+            ..Default::default()
+        };
+        let message = Message::create(input, chat.id, users[0].id, &state.pool).await?;
+        assert_eq!(message.image_meta.len(), 1);
+        let meta = &message.image_meta[0];
+        assert_eq!(meta.url, urls[0]);
+        assert_eq!((meta.width, meta.height), (600, 400));
+        assert_eq!(meta.thumbnails.len(), 1);
+
+        // thumbnails are readable by chat members
+        let thumb_path = ChatFile::path_from_url(&meta.thumbnails[0].url).unwrap();
+        assert!(ChatFile::can_access(thumb_path, users[1].id, &state.pool).await?);
+
+        // already processed images are left as is
+        let again = prepare_images(&state, users[1].id, urls.clone()).await?;
+        assert_eq!(again, urls);
+
+        // others' files are not read, let alone copied
+        let data = rotated_jpeg()?;
+        let path = content_path(&data, "jpg");
+        state.storage.put(&path, data.clone().into()).await?;
+        ChatFile::create(&path, users[0].id, "image/jpeg", data.len() as i64, &state.pool).await?;
+        assert!(prepare_images(&state, users[1].id, vec![ChatFile::url_for(&path)]).await.is_err());
+        assert!(ImageMeta::find_by_path(&path, &state.pool).await?.is_none());
+        Ok(())
+    }
+}
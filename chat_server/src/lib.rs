@@ -1,26 +1,49 @@
+mod commands;
 mod config;
 mod error;
+mod export;
 mod handlers;
+mod images;
+mod mail;
+mod markdown;
+mod mentions;
 mod models;
+mod notify;
+mod retention;
+mod scheduler;
+mod slack;
+mod unfurl;
 mod utils;
 mod middlewares;
+mod storage;
+mod typing;
 
 use anyhow::Context;
+use commands::CommandRegistry;
 use handlers::*;
+use mail::Mailer;
 use sqlx::PgPool;
+use storage::{new_storage, Storage};
+use typing::TypingLimiter;
+use unfurl::Unfurler;
 use std::{
     fmt::{self, Formatter},
     ops::Deref,
     sync::Arc,
+    time::Duration,
 };
 use utils::{DecodingKey, EncodingKey};
 
 pub use error::{AppError,ErrorOutput};
-pub use models::User;
+pub use slack::{import_slack, ImportReport, ImportedChat, SkippedChat, UnmappedUser};
+pub use models::{
+    Avatar, Block, Chat, ChatFile, ChatMute, CodeBlock, ChatRead, ChatRetention, ChatSummary, ChatType, DirectoryUser, ImageMeta, LinkPreview, Mention, Message, MessageFormat, MessageRevision, Pin, PinnedMessage, Presence, PresenceStatus, Profile, Quote, Reaction,
+    ReactionCount, ScheduledMessage, SearchResult, Thumbnail, User, UserStatus,
+};
 pub(crate) use middlewares::{set_layers,verify_token};
 
 use axum::{
-    middleware::from_fn_with_state, routing::{get, patch, post}, Router
+    extract::DefaultBodyLimit, middleware::from_fn_with_state, routing::{delete, get, patch, post, put}, Router
 };
 
 pub use config::AppConfig;
@@ -36,10 +59,18 @@ pub(crate) struct AppStateInner {
     pub(crate) ek: EncodingKey,
     pub(crate) dk: DecodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) commands: CommandRegistry,
+    pub(crate) storage: Arc<dyn Storage>,
+    pub(crate) unfurler: Unfurler,
+    pub(crate) typing: TypingLimiter,
+    pub(crate) mailer: Mailer,
 }
 
 pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
     let state = AppState::try_new(config).await?;
+    scheduler::spawn(state.clone());
+    retention::spawn(state.clone());
+    notify::spawn_pruner(state.pool.clone());
 
     let api = Router::new()
         .route("/chat", get(list_chat_handler).post(create_chat_handler))
@@ -49,12 +80,85 @@ pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
                 .delete(delete_chat_handler)
                 .post(send_msg_handler),
         )
+        .route("/chat/:id/read", post(mark_read_handler))
+        .route("/chat/:id/typing", post(typing_handler))
+        .route(
+            "/chat/:id/mute",
+            put(mute_chat_handler).delete(unmute_chat_handler),
+        )
+        .route("/chat/:id/export", get(export_chat_handler))
+        .route(
+            "/chat/:id/retention",
+            get(get_retention_handler).put(set_retention_handler),
+        )
+        .route(
+            "/chat/:id/legal-hold",
+            put(hold_chat_handler).delete(release_chat_handler),
+        )
+        .route("/chat/:id/pins", get(list_pins_handler))
+        .route("/chat/:id/scheduled", post(create_scheduled_handler))
         .route("/chat/:id/messages", get(list_msg_handler))
+        .route(
+            "/chat/:id/messages/:mid",
+            patch(update_msg_handler).delete(delete_msg_handler),
+        )
+        .route("/chat/:id/messages/:mid/revisions", get(list_msg_revisions_handler))
+        .route("/chat/:id/messages/:mid/forward", post(forward_msg_handler))
+        .route("/chat/:id/messages/:mid/reactions", post(add_reaction_handler))
+        .route(
+            "/chat/:id/messages/:mid/reactions/:emoji",
+            delete(remove_reaction_handler),
+        )
+        .route(
+            "/chat/:id/messages/:mid/pin",
+            post(pin_msg_handler).delete(unpin_msg_handler),
+        )
+        .route("/chat/:id/messages/:mid/thread", get(list_thread_handler))
+        .route(
+            "/chat/:id/messages/:mid/thread/follow",
+            post(follow_thread_handler).delete(unfollow_thread_handler),
+        )
+        .route(
+            "/me",
+            get(get_profile_handler)
+                .patch(update_profile_handler)
+                .delete(delete_account_handler),
+        )
+        .route("/me/export", get(export_account_handler))
+        .route("/me/password", put(change_password_handler))
+        .route("/me/email", post(change_email_handler))
+        .route("/mentions", get(list_mentions_handler))
+        .route("/users", get(list_users_handler))
+        .route("/blocks", get(list_blocks_handler))
+        .route(
+            "/blocks/:id",
+            put(block_user_handler).delete(unblock_user_handler),
+        )
+        .route("/users/presence", get(presence_handler))
+        .route(
+            "/users/me/status",
+            put(set_status_handler).delete(clear_status_handler),
+        )
+        .route("/search", get(search_msg_handler))
+        .route("/scheduled", get(list_scheduled_handler))
+        .route(
+            "/scheduled/:id",
+            patch(update_scheduled_handler).delete(cancel_scheduled_handler),
+        )
+        .route(
+            "/upload",
+            post(upload_handler)
+                .layer(DefaultBodyLimit::max(state.config.storage.max_file_size * MAX_UPLOAD_FILES)),
+        )
+        .route("/upload/presign", post(presign_upload_handler))
+        .route("/upload/confirm", post(confirm_upload_handler))
+        .route("/files/*path", get(file_handler))
         // 认证信息,只对上层的route起作用，在后续生命的route不起作用
         // from_fn_with_state 可以将state和普通方法转换为layer进行拦截
         .layer(from_fn_with_state(state.clone(), verify_token))
         .route("/signin", post(signin_handler))
-        .route("/signup", post(signup_handler));
+        .route("/signup", post(signup_handler))
+        .route("/email/verify", post(verify_email_handler));
 
     let app = Router::new()
         .route("/", get(index_handler))
@@ -79,12 +183,22 @@ impl AppState {
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("connect to db failed")?;
+        let commands = CommandRegistry::new(&config.commands)?;
+        let storage = new_storage(&config.storage)?;
+        let unfurler = Unfurler::spawn(&config.unfurl, pool.clone(), storage.clone());
+        let typing = TypingLimiter::new(Duration::from_secs(config.messages.typing_interval));
+        let mailer = Mailer::new(&config.mail)?;
         Ok(Self {
             inner: Arc::new(AppStateInner {
                 config,
                 ek,
                 dk,
                 pool,
+                commands,
+                storage,
+                unfurler,
+                typing,
+                mailer,
             }),
         })
     }
@@ -114,12 +228,22 @@ impl AppState {
         let server_url = String::from(&config.server.db_url[..post]) + "/test";
         let tdb = TestPg::new(server_url, Path::new("../migrations"));
         let pool = tdb.get_pool().await;
+        let commands = CommandRegistry::new(&config.commands)?;
+        let storage = new_storage(&config.storage)?;
+        let unfurler = Unfurler::spawn(&config.unfurl, pool.clone(), storage.clone());
+        let typing = TypingLimiter::new(Duration::from_secs(config.messages.typing_interval));
+        let mailer = Mailer::new(&config.mail)?;
         let state = Self {
             inner: Arc::new(AppStateInner {
                 config,
                 ek,
                 dk,
                 pool,
+                commands,
+                storage,
+                unfurler,
+                typing,
+                mailer,
             }),
         };
         Ok((tdb, state))
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{config::MailConfig, AppError};
+
+const MAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// hands the emails of the account flows to the mail relay behind `webhook`, without one
+/// they are only logged, the body at debug level, which is enough for local development
+#[derive(Debug)]
+pub(crate) struct Mailer {
+    webhook: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Mailer {
+    pub(crate) fn new(config: &MailConfig) -> Result<Self, AppError> {
+        let client = reqwest::Client::builder().timeout(MAIL_TIMEOUT).build()?;
+        Ok(Self {
+            webhook: config.webhook.clone(),
+            client,
+        })
+    }
+
+    pub(crate) async fn send(&self, mail: &Mail) -> Result<(), AppError> {
+        let Some(webhook) = &self.webhook else {
+            // the body carries secrets like verification tokens
+            info!("mail to {}: {}", mail.to, mail.subject);
+            debug!("mail body:\n{}", mail.body);
+            return Ok(());
+        };
+        self.client
+            .post(webhook)
+            .json(mail)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
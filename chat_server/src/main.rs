@@ -1,15 +1,29 @@
-use anyhow::Result;
-use chat_server::{get_router, AppConfig};
+use std::fs::File;
+
+use anyhow::{bail, Result};
+use chat_server::{get_router, import_slack, AppConfig};
+use sqlx::PgPool;
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
+const USAGE: &str = "usage: chat-server [import-slack <export.zip> [--dry-run]]";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let layer = Layer::new().pretty().with_filter(LevelFilter::INFO);
     tracing_subscriber::registry().with(layer).init();
 
     let config = AppConfig::load()?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => serve(config).await,
+        Some("import-slack") => import(config, &args[1..]).await,
+        Some(_) => bail!(USAGE),
+    }
+}
+
+async fn serve(config: AppConfig) -> Result<()> {
     let addr = format!("127.0.0.1:{}", config.server.port);
 
     let app = get_router(config).await?;
@@ -20,3 +34,17 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// import a slack export and print the report, see `import_slack`
+async fn import(config: AppConfig, args: &[String]) -> Result<()> {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        bail!(USAGE);
+    };
+
+    let pool = PgPool::connect(&config.server.db_url).await?;
+    let report = import_slack(File::open(path)?, dry_run, &pool).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    Ok(())
+}
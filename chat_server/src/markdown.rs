@@ -0,0 +1,197 @@
+use std::{collections::HashSet, ops::Range};
+
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+use crate::{models::MessageFormat, AppError, CodeBlock};
+
+/// in characters, for every format
+pub(crate) const MAX_CONTENT_LEN: usize = 4000;
+
+/// what is derived from the content of a message when it is written
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Rendered {
+    /// sanitized html, only for markdown
+    pub html: Option<String>,
+    /// the http(s) links, in order of appearance
+    pub links: Vec<String>,
+    pub code_blocks: Vec<CodeBlock>,
+}
+
+pub(crate) fn check_length(content: &str) -> Result<(), AppError> {
+    let len = content.chars().count();
+    if len > MAX_CONTENT_LEN {
+        return Err(AppError::MessageTooLong(len, MAX_CONTENT_LEN));
+    }
+    Ok(())
+}
+
+pub(crate) fn render(content: &str, format: MessageFormat) -> Result<Rendered, AppError> {
+    check_length(content)?;
+    let rendered = match format {
+        MessageFormat::Plain => {
+            let mut links = vec![];
+            find_links(content, &mut links);
+            Rendered {
+                links,
+                ..Default::default()
+            }
+        }
+        MessageFormat::Markdown => render_markdown(content),
+    };
+    Ok(rendered)
+}
+
+/// byte ranges of the inline code and code blocks, nothing in there is a mention
+pub(crate) fn code_ranges(content: &str, format: MessageFormat) -> Vec<Range<usize>> {
+    if format != MessageFormat::Markdown {
+        return vec![];
+    }
+    Parser::new_ext(content, options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Code(_) | Event::Start(Tag::CodeBlock(_)) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH
+}
+
+fn render_markdown(content: &str) -> Rendered {
+    // raw html is shown as text, never passed through
+    let events: Vec<Event> = Parser::new_ext(content, options())
+        .map(|event| match event {
+            Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+            event => event,
+        })
+        .collect();
+
+    let mut links = vec![];
+    let mut code_blocks = vec![];
+    let mut code_block: Option<CodeBlock> = None;
+    for event in &events {
+        match (event, code_block.as_mut()) {
+            (Event::Start(Tag::CodeBlock(kind)), _) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().map(str::to_string),
+                    CodeBlockKind::Indented => None,
+                };
+                code_block = Some(CodeBlock {
+                    lang,
+                    code: String::new(),
+                });
+            }
+            (Event::End(TagEnd::CodeBlock), _) => code_blocks.extend(code_block.take()),
+            (Event::Text(text), Some(block)) => block.code.push_str(text),
+            (Event::Text(text), None) => find_links(text, &mut links),
+            (Event::Start(Tag::Link { dest_url, .. }), _) if is_web_link(dest_url) => {
+                push_link(dest_url.to_string(), &mut links);
+            }
+            _ => {}
+        }
+    }
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    Rendered {
+        html: Some(sanitize(&unsafe_html)),
+        links,
+        code_blocks,
+    }
+}
+
+/// drop scripts, event handlers and non web urls. Remote images are dropped too, they
+/// would leak the address of every reader to whoever hosts them
+fn sanitize(html: &str) -> String {
+    ammonia::Builder::default()
+        .rm_tags(["img"])
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(html)
+        .to_string()
+}
+
+/// bare urls in text, e.g. `see https://example.com.`
+fn find_links(text: &str, links: &mut Vec<String>) {
+    for word in text.split_whitespace() {
+        let Some(start) = word.find("https://").or_else(|| word.find("http://")) else {
+            continue;
+        };
+        let url = word[start..].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '}', '\'', '"', '>']);
+        if url.len() > "https://".len() {
+            push_link(url.to_string(), links);
+        }
+    }
+}
+
+fn push_link(url: String, links: &mut Vec<String>) {
+    if !links.contains(&url) {
+        links.push(url);
+    }
+}
+
+fn is_web_link(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn render_plain_should_only_extract_links() -> Result<()> {
+        let rendered = render("see https://example.com/a, or (http://x.io/b).", MessageFormat::Plain)?;
+        assert_eq!(rendered.html, None);
+        assert_eq!(rendered.links, ["https://example.com/a", "http://x.io/b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn render_markdown_should_sanitize() -> Result<()> {
+        let content = "**hi** <script>alert(1)</script> [x](javascript:alert(1)) [docs](https://docs.rs)\n\n![t](https://evil.io/t.png)";
+        let html = render(content, MessageFormat::Markdown)?.html.unwrap();
+        assert!(html.contains("<strong>hi</strong>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("<img"));
+        assert!(html.contains(r#"<a href="https://docs.rs" rel="noopener noreferrer nofollow">docs</a>"#));
+        Ok(())
+    }
+
+    #[test]
+    fn render_markdown_should_extract_code_and_links() -> Result<()> {
+        let content = "read [the guide](https://a.io/guide) or https://b.io\n\n```rust\nlet url = \"https://c.io\";\n```\n";
+        let rendered = render(content, MessageFormat::Markdown)?;
+        assert_eq!(rendered.links, ["https://a.io/guide", "https://b.io"]);
+        assert_eq!(
+            rendered.code_blocks,
+            [CodeBlock {
+                lang: Some("rust".to_string()),
+                code: "let url = \"https://c.io\";\n".to_string(),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn code_ranges_should_cover_code() {
+        let content = "hi @alice `@bob`";
+        let ranges = code_ranges(content, MessageFormat::Markdown);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(&content[ranges[0].clone()], "`@bob`");
+        assert!(code_ranges(content, MessageFormat::Plain).is_empty());
+    }
+
+    #[test]
+    fn too_long_content_should_fail() {
+        let content = "a".repeat(MAX_CONTENT_LEN + 1);
+        let ret = render(&content, MessageFormat::Plain);
+        assert!(matches!(ret, Err(AppError::MessageTooLong(4001, MAX_CONTENT_LEN))));
+        assert!(check_length(&"好".repeat(MAX_CONTENT_LEN)).is_ok());
+    }
+}
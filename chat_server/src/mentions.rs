@@ -0,0 +1,115 @@
+use crate::{markdown, AppError, AppState, Chat, Mention, Message, User};
+
+const CHANNEL: &str = "channel";
+
+/// parse and record the mentions of a message sent or edited in the chat.
+/// Returns the users mentioned for the first time, they get a mention event
+pub(crate) async fn update_mentions(
+    state: &AppState,
+    chat: &Chat,
+    message: &mut Message,
+) -> Result<Vec<i64>, AppError> {
+    let mentions = if message.content.contains('@') {
+        let members = User::fetch_by_ids(&chat.members, &state.pool).await?;
+        let code = markdown::code_ranges(&message.content, message.format);
+        let mut mentions = parse(&message.content, &members);
+        mentions.retain(|m| !code.iter().any(|range| range.contains(&m.start)));
+        mentions
+    } else {
+        vec![]
+    };
+    if mentions.is_empty() && message.mentions.is_empty() {
+        return Ok(vec![]);
+    }
+    message.save_mentions(mentions, &chat.members, &state.pool).await
+}
+
+/// find `@channel`, `@<user id>` and `@<fullname>` mentions of chat members
+pub(crate) fn parse(content: &str, members: &[User]) -> Vec<Mention> {
+    let mut mentions = vec![];
+    let mut pos = 0;
+    while let Some(i) = content[pos..].find('@') {
+        let start = pos + i;
+        pos = start + 1;
+        // part of a word, e.g. an email address
+        if content[..start].chars().next_back().is_some_and(is_word_char) {
+            continue;
+        }
+        if let Some((user_id, len)) = match_mention(&content[pos..], members) {
+            pos += len;
+            mentions.push(Mention {
+                user_id,
+                start,
+                end: pos,
+            });
+        }
+    }
+    mentions
+}
+
+fn match_mention(rest: &str, members: &[User]) -> Option<(Option<i64>, usize)> {
+    let word_len = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+    let word = &rest[..word_len];
+    if word == CHANNEL {
+        return Some((None, word_len));
+    }
+    if let Ok(id) = word.parse::<i64>() {
+        return members.iter().any(|u| u.id == id).then_some((Some(id), word_len));
+    }
+    // fullnames may contain spaces, the longest one matching wins
+    members
+        .iter()
+        .filter(|u| !u.fullname.is_empty() && matches_name(rest, &u.fullname))
+        .max_by_key(|u| u.fullname.len())
+        .map(|u| (Some(u.id), u.fullname.len()))
+}
+
+fn matches_name(rest: &str, name: &str) -> bool {
+    let Some(prefix) = rest.get(..name.len()) else {
+        return false;
+    };
+    prefix.to_lowercase() == name.to_lowercase()
+        && !rest[name.len()..].chars().next().is_some_and(is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members() -> Vec<User> {
+        vec![
+            User::new(1, "Alice", "alice@test.com"),
+            User::new(2, "Alice Liddell", "liddell@test.com"),
+            User::new(3, "Bob", "bob@test.com"),
+        ]
+    }
+
+    #[test]
+    fn parse_mentions_should_work() {
+        let content = "@alice liddell and @Bob, ask @3 or @channel";
+        let mentions = parse(content, &members());
+        let found: Vec<_> = mentions
+            .iter()
+            .map(|m| (m.user_id, &content[m.start..m.end]))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Some(2), "@alice liddell"),
+                (Some(3), "@Bob"),
+                (Some(3), "@3"),
+                (None, "@channel"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_mentions_should_skip_non_members() {
+        let mentions = parse("mail bob@example.com, @Bobby @42 @Alicex @ é@Bob", &members());
+        assert!(mentions.is_empty());
+    }
+}
@@ -4,7 +4,7 @@ use axum_extra::{headers::{authorization::Bearer, Authorization}, TypedHeader};
 use serde::Deserialize;
 use tracing::warn;
 
-use crate::AppState;
+use crate::{AppState, User};
 
 
 #[derive(Debug, Deserialize)]
@@ -41,6 +41,16 @@ pub(crate) async fn verify_token(
 
       let req = match state.dk.verify(&token) {
       Ok(user) => {
+          // tokens outlive the account, deleting it revokes them
+          match User::is_deleted(user.id, &state.pool).await {
+              Ok(false) => {}
+              Ok(true) => {
+                  let msg = format!("user {} was deleted", user.id);
+                  warn!(msg);
+                  return (StatusCode::FORBIDDEN, msg).into_response();
+              }
+              Err(e) => return e.into_response(),
+          }
           let mut req = Request::from_parts(parts, body);
           req.extensions_mut().insert(user);
           req
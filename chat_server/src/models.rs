@@ -1,9 +1,37 @@
+mod account;
+mod block;
+mod chat;
+mod directory;
+mod file;
+mod forward;
+mod image;
+mod messages;
+mod mute;
+mod pin;
+mod presence;
+mod preview;
+mod profile;
+mod reaction;
+mod retention;
+mod scheduled;
+mod search;
 mod user;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{types::Json, FromRow};
 
+pub use account::*;
+pub use chat::*;
+pub use directory::*;
+pub use forward::*;
+pub use messages::*;
+pub use mute::*;
+pub use presence::*;
+pub use retention::*;
+pub use profile::*;
+pub use scheduled::*;
+pub use search::*;
 pub use user::*;
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
@@ -16,3 +44,347 @@ pub struct User {
     pub password_hash: Option<String>,
     pub created_at: DateTime<Utc>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "chat_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatType {
+    Single,
+    Group,
+    PrivateChannel,
+    PublicChannel,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "message_format", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    #[default]
+    Plain,
+    Markdown,
+}
+
+/// the account of the signed in user, `fullname` is the name displayed to others
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Profile {
+    pub id: i64,
+    pub fullname: String,
+    pub email: String,
+    /// the new address waiting to be verified
+    pub pending_email: Option<String>,
+    pub title: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub avatar: Option<Json<Avatar>>,
+    /// others can see the email in the directory
+    pub show_email: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// a user as listed in the directory
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct DirectoryUser {
+    pub id: i64,
+    pub fullname: String,
+    /// None unless the user shows it
+    pub email: Option<String>,
+    pub title: Option<String>,
+    pub avatar: Option<Json<Avatar>>,
+}
+
+/// a square image with smaller variants, readable by every user
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Avatar {
+    pub url: String,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+/// status a user sets on themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "user_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+    Away,
+    /// do not disturb
+    Dnd,
+}
+
+/// what others see, a connected user shows the status they set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Dnd,
+    Offline,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Chat {
+    pub id: i64,
+    pub name: String,
+    pub r#type: ChatType,
+    pub members: Vec<i64>,
+    /// the creator, None for chats created before owners were recorded
+    pub owner_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// a chat as listed for a user, with the read state of the user
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub chat: Chat,
+    /// messages from others after the last read one, thread replies excluded
+    pub unread_count: i64,
+    pub last_read_id: Option<i64>,
+    /// the user gets no new message events from the chat
+    pub muted: bool,
+    /// None if muted until unmuted
+    pub muted_until: Option<DateTime<Utc>>,
+    #[sqlx(skip)]
+    pub last_message: Option<Message>,
+}
+
+/// presence of a user, tracked by the notify servers from the open event streams
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Presence {
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    pub status_text: Option<String>,
+    /// when the status and its text are cleared, None if they are kept until cleared
+    pub status_expires_at: Option<DateTime<Utc>>,
+    /// when the user was last connected, None if they never were
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Block {
+    pub blocker_id: i64,
+    pub blocked_id: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatMute {
+    pub chat_id: i64,
+    pub user_id: i64,
+    /// None if muted until unmuted
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// how long the messages of a chat are kept
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatRetention {
+    pub chat_id: i64,
+    /// None follows the server default, 0 keeps messages forever
+    pub retention_days: Option<i32>,
+    /// the retention applied, None when nothing is purged
+    #[sqlx(skip)]
+    pub effective_days: Option<i32>,
+    /// nothing is purged while set
+    pub legal_hold: bool,
+    pub legal_hold_by: Option<i64>,
+    pub legal_hold_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatRead {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub last_read_id: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Message {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub images: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+    /// set when the message was deleted, content and images are cleared then
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub deleted_by: Option<i64>,
+    pub reply_to: Option<i64>,
+    /// the first message of the thread this message replies in
+    pub thread_root_id: Option<i64>,
+    /// number of replies in the thread, set on the root
+    pub reply_count: i32,
+    pub last_reply_at: Option<DateTime<Utc>>,
+    pub mentions: Json<Vec<Mention>>,
+    /// the forwarded or quoted message
+    pub quote: Option<Json<Quote>>,
+    pub format: MessageFormat,
+    /// sanitized html of markdown messages
+    pub html: Option<String>,
+    /// http(s) links in the content
+    pub links: Vec<String>,
+    pub code_blocks: Json<Vec<CodeBlock>>,
+    /// link previews, filled in shortly after the message is sent
+    pub previews: Json<Vec<LinkPreview>>,
+    /// metadata of the images, in the same order as `images`
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub image_meta: Vec<ImageMeta>,
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<ReactionCount>,
+    /// the viewer blocked the sender, clients collapse the message
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sender_blocked: bool,
+    /// the recipient of the event muted the chat, clients deliver it silently
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub muted: bool,
+}
+
+/// attribution and content of a quoted message, as it was when it was quoted
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Quote {
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub content: String,
+    #[serde(default)]
+    pub format: MessageFormat,
+    /// the quoted message was deleted since, its content is cleared
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// open graph metadata of a link
+#[derive(Debug, Clone, Default, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// `/api/files/...` url of the copy in the storage, never the remote image
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CodeBlock {
+    /// the info string of fenced blocks, e.g. `rust`
+    pub lang: Option<String>,
+    pub code: String,
+}
+
+/// a message matching a search
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct SearchResult {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    pub rank: f32,
+    /// html escaped fragments of the content, matches are wrapped in `<mark>`
+    pub snippet: String,
+}
+
+/// a mention in the content of a message, `start..end` is the byte range of `@name`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Mention {
+    /// None for @channel
+    pub user_id: Option<i64>,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Reaction {
+    pub message_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// a message to send later, the row is removed once the message is sent
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub images: Vec<String>,
+    pub reply_to: Option<i64>,
+    pub format: MessageFormat,
+    pub send_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// failed sends so far, the message is no longer retried after `MAX_SEND_ATTEMPTS`
+    pub attempts: i32,
+    pub failed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Pin {
+    pub message_id: i64,
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+}
+
+/// a pinned message with who pinned it and when
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct PinnedMessage {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+}
+
+/// reactions to a message aggregated by emoji
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    /// in the order they reacted
+    pub user_ids: Vec<i64>,
+}
+
+/// content of a message before it was edited
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct MessageRevision {
+    pub id: i64,
+    pub message_id: i64,
+    pub content: String,
+    /// when this content was replaced
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatFile {
+    pub path: String,
+    pub uploader_id: i64,
+    pub content_type: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ImageMeta {
+    #[serde(skip)]
+    pub path: String,
+    #[sqlx(skip)]
+    pub url: String,
+    pub width: i32,
+    pub height: i32,
+    pub blurhash: String,
+    pub thumbnails: Json<Vec<Thumbnail>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Thumbnail {
+    /// the size it was generated for, the longer side is at most this
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub url: String,
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use sqlx::{types::Json, PgPool};
+
+use crate::{AppError, ChatFile, ImageMeta, Message, Thumbnail};
+
+impl ImageMeta {
+    pub async fn create(
+        path: &str,
+        width: u32,
+        height: u32,
+        blurhash: &str,
+        thumbnails: Vec<Thumbnail>,
+        pool: &PgPool,
+    ) -> Result<Self, AppError> {
+        let meta: Self = sqlx::query_as(
+            r#"
+            INSERT INTO images (path, width, height, blurhash, thumbnails)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (path) DO UPDATE SET thumbnails = EXCLUDED.thumbnails
+            RETURNING path, width, height, blurhash, thumbnails
+            "#,
+        )
+        .bind(path)
+        .bind(width as i32)
+        .bind(height as i32)
+        .bind(blurhash)
+        .bind(Json(thumbnails))
+        .fetch_one(pool)
+        .await?;
+
+        Ok(meta.with_url())
+    }
+
+    pub async fn find_by_path(path: &str, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        let meta: Option<Self> = sqlx::query_as(
+            "SELECT path, width, height, blurhash, thumbnails FROM images WHERE path = $1",
+        )
+        .bind(path)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(meta.map(Self::with_url))
+    }
+
+    pub async fn fetch_by_paths(paths: &[&str], pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let metas: Vec<Self> = sqlx::query_as(
+            "SELECT path, width, height, blurhash, thumbnails FROM images WHERE path = ANY($1)",
+        )
+        .bind(paths)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(metas.into_iter().map(Self::with_url).collect())
+    }
+
+    fn with_url(mut self) -> Self {
+        self.url = ChatFile::url_for(&self.path);
+        self
+    }
+}
+
+impl Message {
+    /// fill in `image_meta` for the images of the messages with a single query
+    pub async fn load_image_meta(messages: &mut [Message], pool: &PgPool) -> Result<(), AppError> {
+        let paths: Vec<&str> = messages
+            .iter()
+            .flat_map(|m| m.images.iter())
+            .filter_map(|url| ChatFile::path_from_url(url))
+            .collect();
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let metas: HashMap<String, ImageMeta> = ImageMeta::fetch_by_paths(&paths, pool)
+            .await?
+            .into_iter()
+            .map(|m| (m.url.clone(), m))
+            .collect();
+        for message in messages.iter_mut() {
+            message.image_meta = message
+                .images
+                .iter()
+                .filter_map(|url| metas.get(url).cloned())
+                .collect();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use sqlx::{types::Json, PgPool};
+
+use crate::{models::messages::MESSAGE_COLUMNS, AppError, LinkPreview, Message};
+
+/// failed fetches are retried sooner than previews are refreshed
+const FAILED_TTL: u64 = 10 * 60;
+
+impl LinkPreview {
+    /// the cached preview of the url if it was fetched less than `ttl` seconds ago
+    pub async fn find_cached(url: &str, ttl: u64, pool: &PgPool) -> Result<Option<Self>, AppError> {
+        let preview = sqlx::query_as(
+            r#"
+            SELECT url, title, description, image, site_name
+            FROM link_previews
+            WHERE url = $1 AND fetched_at > NOW() - make_interval(secs => CASE
+                WHEN title IS NULL AND description IS NULL AND image IS NULL THEN $3
+                ELSE $2
+            END)
+            "#,
+        )
+        .bind(url)
+        .bind(ttl as f64)
+        .bind(FAILED_TTL.min(ttl) as f64)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(preview)
+    }
+
+    pub async fn save(&self, pool: &PgPool) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO link_previews (url, title, description, image, site_name)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (url) DO UPDATE
+            SET title = EXCLUDED.title, description = EXCLUDED.description, image = EXCLUDED.image,
+                site_name = EXCLUDED.site_name, fetched_at = NOW()
+            "#,
+        )
+        .bind(&self.url)
+        .bind(&self.title)
+        .bind(&self.description)
+        .bind(&self.image)
+        .bind(&self.site_name)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// nothing to show, the page had no metadata or could not be fetched
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none() && self.image.is_none()
+    }
+}
+
+impl Message {
+    /// attach the previews unless the message was edited or deleted in the meantime,
+    /// `links` are the links the previews were fetched for
+    pub async fn set_previews(
+        id: i64,
+        links: &[String],
+        previews: Vec<LinkPreview>,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, AppError> {
+        let message: Option<Message> = sqlx::query_as(&format!(
+            r#"
+            UPDATE messages SET previews = $3
+            WHERE id = $1 AND links = $2 AND deleted_at IS NULL
+            RETURNING {MESSAGE_COLUMNS}
+            "#
+        ))
+        .bind(id)
+        .bind(links)
+        .bind(Json(previews))
+        .fetch_optional(pool)
+        .await?;
+
+        let Some(mut message) = message else {
+            return Ok(None);
+        };
+        Message::load_image_meta(std::slice::from_mut(&mut message), pool).await?;
+        Message::load_reactions(std::slice::from_mut(&mut message), pool).await?;
+        Ok(Some(message))
+    }
+}
@@ -33,6 +33,16 @@ impl User {
         Ok(user)
     }
 
+    pub async fn fetch_by_ids(ids: &[i64], pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let users = sqlx::query_as(
+            "SELECT id, fullname, email, created_at FROM users WHERE id = ANY($1) ORDER BY id",
+        )
+        .bind(ids)
+        .fetch_all(pool)
+        .await?;
+        Ok(users)
+    }
+
     /// create a new user
     pub async fn create(input: &CreateUser, pool: &PgPool) -> Result<Self, AppError> {
         // check email exists
@@ -55,7 +65,7 @@ impl User {
         pool: &PgPool,
     ) -> Result<Option<Self>, AppError> {
         let user: Option<User> = sqlx::query_as(
-            "SELECT id, fullname, email, password_hash, created_at FROM users WHERE email = $1",
+            "SELECT id, fullname, email, password_hash, created_at FROM users WHERE email = $1 AND deleted_at IS NULL",
         )
         .bind(&input.email)
         .fetch_optional(pool)
@@ -75,7 +85,7 @@ impl User {
     }
 }
 
-fn hash_password(password: &str) -> Result<String, AppError> {
+pub(super) fn hash_password(password: &str) -> Result<String, AppError> {
     let salt = SaltString::generate(&mut OsRng);
 
     let argon2 = Argon2::default();
@@ -87,7 +97,7 @@ fn hash_password(password: &str) -> Result<String, AppError> {
     Ok(password_hash)
 }
 
-fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError> {
+pub(super) fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError> {
     let argon2 = Argon2::default();
     let password_hash = argon2::PasswordHash::new(password_hash)?;
 
@@ -178,6 +188,16 @@ impl User {
             created_at: chrono::Utc::now(),
         }
     }
+
+    /// create `n` users named user0..user{n-1} for tests that need chat members
+    pub async fn create_for_test(n: usize, pool: &PgPool) -> Result<Vec<Self>, AppError> {
+        let mut users = Vec::with_capacity(n);
+        for i in 0..n {
+            let input = CreateUser::new(&format!("user{}", i), &format!("user{}@test.com", i), "password");
+            users.push(User::create(&input, pool).await?);
+        }
+        Ok(users)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::{AppError, AppState, Chat, ChatFile, Message};
+
+/// delete the messages past the retention of their chat, every replica runs one
+pub(crate) fn spawn(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let period = Duration::from_secs(state.config.retention.purge_interval.max(1));
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = purge(&state).await {
+                warn!("purge expired messages failed: {}", e);
+            }
+        }
+    })
+}
+
+/// purge chat after chat, batch after batch until nothing is left, returns how many
+/// messages were deleted
+pub(crate) async fn purge(state: &AppState) -> Result<usize, AppError> {
+    let config = &state.config.retention;
+    let batch_size = config.batch_size.max(1);
+    let mut total = 0;
+    for chat_id in Chat::list_expired(config.default_days, &state.pool).await? {
+        loop {
+            let purged = Message::purge_expired(chat_id, config.default_days, batch_size, &state.pool).await?;
+            total += purged.count;
+            for key in ChatFile::purge(&purged.images, &state.pool).await? {
+                if let Err(e) = state.storage.delete(&key).await {
+                    warn!("delete file {} failed: {}", key, e);
+                }
+            }
+            if (purged.count as i64) < batch_size {
+                break;
+            }
+            // let the writers waiting on the rows in between batches
+            tokio::task::yield_now().await;
+        }
+    }
+    if total > 0 {
+        info!("purged {} expired messages", total);
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        storage::content_path,
+        AppConfig, User,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn purge_should_delete_attachments() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        let dir = tempfile::tempdir()?;
+        config.storage.base_dir = dir.path().to_path_buf();
+        config.retention.default_days = Some(30);
+        config.retention.batch_size = 1;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(2, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+
+        let data = b"old notes".to_vec();
+        let path = content_path(&data, "txt");
+        state.storage.put(&path, data.clone().into()).await?;
+        ChatFile::create(&path, users[0].id, "text/plain", data.len() as i64, &state.pool).await?;
+        let input = CreateMessage {
+            images: vec![ChatFile::url_for(&path)],
+            ..CreateMessage::new("notes")
+        };
+        Message::create(input, chat.id, users[0].id, &state.pool).await?;
+        Message::create(CreateMessage::new("old"), chat.id, users[1].id, &state.pool).await?;
+        sqlx::query("UPDATE messages SET created_at = NOW() - INTERVAL '31 days'")
+            .execute(&state.pool)
+            .await?;
+        Message::create(CreateMessage::new("new"), chat.id, users[1].id, &state.pool).await?;
+
+        assert_eq!(purge(&state).await?, 2);
+        assert!(state.storage.get(&path).await?.is_none());
+        assert!(!ChatFile::can_access(&path, users[0].id, &state.pool).await?);
+        assert_eq!(purge(&state).await?, 0);
+        Ok(())
+    }
+}
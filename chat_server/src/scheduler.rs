@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+use tracing::warn;
+
+use crate::{handlers::publish_message, AppError, AppState, Chat, ScheduledMessage};
+
+/// send the scheduled messages once they are due, every replica runs one
+pub(crate) fn spawn(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let period = Duration::from_secs(state.config.messages.schedule_interval.max(1));
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = send_due(&state).await {
+                warn!("send scheduled messages failed: {}", e);
+            }
+        }
+    })
+}
+
+/// returns how many messages were sent
+pub(crate) async fn send_due(state: &AppState) -> Result<usize, AppError> {
+    let messages = ScheduledMessage::deliver_due(&state.pool).await?;
+    let count = messages.len();
+    for mut message in messages {
+        // already stored, a failure here only loses the notification
+        let Some(chat) = Chat::get_by_id(message.chat_id, &state.pool).await? else {
+            continue;
+        };
+        if let Err(e) = publish_message(state, &chat, &mut message).await {
+            warn!("publish scheduled message {} failed: {}", message.id, e);
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chrono::{Duration, Utc};
+    use sqlx::postgres::PgListener;
+
+    use crate::{
+        models::{CreateChat, CreateScheduledMessage},
+        notify::{recv_event, NOTIFY_CHANNEL},
+        AppConfig, User,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn send_due_should_notify_members() -> Result<()> {
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(2, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        let input = CreateScheduledMessage {
+            content: "good morning".to_string(),
+            send_at: Utc::now() + Duration::hours(1),
+            ..Default::default()
+        };
+        ScheduledMessage::create(input, chat.id, users[0].id, &state.pool).await?;
+        sqlx::query("UPDATE scheduled_messages SET send_at = NOW()")
+            .execute(&state.pool)
+            .await?;
+
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+        assert_eq!(send_due(&state).await?, 1);
+        assert_eq!(send_due(&state).await?, 0);
+
+        let payload = recv_event(&mut listener, &state.pool).await?;
+        assert_eq!(payload["event"]["type"], "new_message");
+        assert_eq!(payload["event"]["content"], "good morning");
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+mod local;
+mod s3;
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{StorageBackend, StorageConfig},
+    AppError,
+};
+
+pub(crate) use local::LocalStorage;
+pub(crate) use s3::S3Storage;
+
+/// a request the client sends directly to the storage backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresignedRequest {
+    pub method: String,
+    pub url: String,
+    /// headers the client must send as is, they are part of the signature
+    pub headers: BTreeMap<String, String>,
+}
+
+/// blob store for uploaded files, keys are content addressed paths built by [`content_path`]
+#[async_trait]
+pub(crate) trait Storage: fmt::Debug + Send + Sync {
+    /// store the data under key, a no-op if the key already exists
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), AppError>;
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, AppError>;
+
+    /// size of the data under key in bytes, None if it does not exist
+    async fn head(&self, key: &str) -> Result<Option<u64>, AppError>;
+
+    /// at most the first `len` bytes of the data under key, None if it does not exist
+    async fn get_prefix(&self, key: &str, len: usize) -> Result<Option<Bytes>, AppError>;
+
+    /// remove the data under key, a no-op if it does not exist
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    /// a url the client can download the file from directly, None if files are served by us.
+    /// The download is served as `content_type` whatever the object was stored with
+    async fn presign_get(&self, _key: &str, _content_type: &str) -> Result<Option<String>, AppError> {
+        Ok(None)
+    }
+
+    /// a request the client can upload the file with directly, None if not supported.
+    /// `sha256` is the hex digest of the content, the backend rejects any other content
+    async fn presign_put(
+        &self,
+        _key: &str,
+        _sha256: &str,
+        _size: usize,
+        _content_type: &str,
+    ) -> Result<Option<PresignedRequest>, AppError> {
+        Ok(None)
+    }
+}
+
+pub(crate) fn new_storage(config: &StorageConfig) -> Result<Arc<dyn Storage>, AppError> {
+    let storage: Arc<dyn Storage> = match &config.backend {
+        StorageBackend::Local => Arc::new(LocalStorage::new(&config.base_dir)),
+        StorageBackend::S3(s3) => Arc::new(S3Storage::try_new(s3)?),
+    };
+    Ok(storage)
+}
+
+/// sha256 of the data split into `abc/def/<rest>.<ext>` to keep directories small
+pub(crate) fn content_path(data: &[u8], ext: &str) -> String {
+    hash_path(&format!("{:x}", Sha256::digest(data)), ext)
+}
+
+/// storage path of a hex sha256 digest, see [`content_path`]
+pub(crate) fn hash_path(hash: &str, ext: &str) -> String {
+    let (p1, rest) = hash.split_at(3);
+    let (p2, p3) = rest.split_at(3);
+    if ext.is_empty() {
+        format!("{}/{}/{}", p1, p2, p3)
+    } else {
+        format!("{}/{}/{}.{}", p1, p2, p3, ext)
+    }
+}
+
+/// thumbnails live next to the original as `<path>.t<size>.<ext>`
+pub(crate) fn thumbnail_path(path: &str, size: u32, ext: &str) -> String {
+    format!("{}.t{}.{}", path, size, ext)
+}
+
+/// the original image of a thumbnail path, None if it is not a thumbnail
+pub(crate) fn thumbnail_source(path: &str) -> Option<&str> {
+    let (rest, _ext) = path.rsplit_once('.')?;
+    let (source, size) = rest.rsplit_once(".t")?;
+    let is_size = !size.is_empty() && size.chars().all(|c| c.is_ascii_digit());
+    (is_size && !source.is_empty()).then_some(source)
+}
+
+/// reject keys that could escape the storage root
+pub(crate) fn validate_key(key: &str) -> Result<(), AppError> {
+    let valid = !key.is_empty()
+        && key
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..")
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '/' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::ChatFileError(format!("invalid file path: {}", key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_path_should_work() {
+        let path = content_path(b"hello", "txt");
+        assert_eq!(
+            path,
+            "2cf/24d/ba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824.txt"
+        );
+        assert!(validate_key(&path).is_ok());
+        assert!(validate_key("../etc/passwd").is_err());
+        assert!(validate_key("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn thumbnail_path_should_work() {
+        let path = thumbnail_path("2cf/24d/ba5f.png", 256, "png");
+        assert_eq!(path, "2cf/24d/ba5f.png.t256.png");
+        assert_eq!(thumbnail_source(&path), Some("2cf/24d/ba5f.png"));
+        assert_eq!(thumbnail_source("2cf/24d/ba5f.png"), None);
+        assert_eq!(thumbnail_source("2cf/24d/ba5f.txt.tx.png"), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use tokio::{fs, io::AsyncReadExt};
+use uuid::Uuid;
+
+use crate::AppError;
+
+use super::{validate_key, Storage};
+
+/// stores files on the local disk under `base_dir`
+#[derive(Debug)]
+pub(crate) struct LocalStorage {
+    base_dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            base_dir: base_dir.into(),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, AppError> {
+        validate_key(key)?;
+        Ok(self.base_dir.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        if fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // write to a temp file first so readers never see a partial file
+        let tmp = path.with_extension(format!("{}.tmp", Uuid::now_v7()));
+        fs::write(&tmp, &data).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, AppError> {
+        match fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<u64>, AppError> {
+        match fs::metadata(self.path(key)?).await {
+            Ok(meta) => Ok(Some(meta.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_prefix(&self, key: &str, len: usize) -> Result<Option<Bytes>, AppError> {
+        let file = match fs::File::open(self.path(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut data = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut data).await?;
+        Ok(Some(data.into()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::storage::content_path;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn local_storage_should_work() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = LocalStorage::new(dir.path());
+        let key = content_path(b"hello", "txt");
+
+        storage.put(&key, Bytes::from_static(b"hello")).await?;
+        storage.put(&key, Bytes::from_static(b"hello")).await?;
+        assert_eq!(storage.get(&key).await?, Some(Bytes::from_static(b"hello")));
+        assert_eq!(storage.get(&content_path(b"world", "txt")).await?, None);
+        assert_eq!(storage.head(&key).await?, Some(5));
+        assert_eq!(storage.get_prefix(&key, 2).await?, Some(Bytes::from_static(b"he")));
+        assert_eq!(storage.head(&content_path(b"world", "txt")).await?, None);
+        assert!(storage.get("../secret").await.is_err());
+
+        storage.delete(&key).await?;
+        storage.delete(&key).await?;
+        assert_eq!(storage.get(&key).await?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// signals tracked before the stale ones are dropped
+const MAX_TRACKED: usize = 10_000;
+
+/// lets a user signal typing at most once per `interval`, whichever chat it is in, so that
+/// the limit can not be multiplied by spreading signals over chats. Kept in memory, each
+/// replica limits the requests it serves.
+pub(crate) struct TypingLimiter {
+    interval: Duration,
+    last: Mutex<HashMap<i64, Instant>>,
+}
+
+impl TypingLimiter {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// returns false if the user already signaled typing within the interval
+    pub(crate) fn allow(&self, user_id: i64) -> bool {
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        if last
+            .get(&user_id)
+            .is_some_and(|at| now.duration_since(*at) < self.interval)
+        {
+            return false;
+        }
+        if last.len() >= MAX_TRACKED {
+            last.retain(|_, at| now.duration_since(*at) < self.interval);
+        }
+        last.insert(user_id, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiter_should_allow_one_signal_per_interval() {
+        let limiter = TypingLimiter::new(Duration::from_millis(50));
+        assert!(limiter.allow(1));
+        assert!(!limiter.allow(1));
+        // other users are limited on their own
+        assert!(limiter.allow(2));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(limiter.allow(1));
+    }
+}
@@ -0,0 +1,596 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE, LOCATION},
+    redirect::Policy,
+    Response, Url,
+};
+use sqlx::PgPool;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::warn;
+
+use crate::{
+    config::UnfurlConfig,
+    handlers::sniff_content_type,
+    notify::{notify_message, ChatEvent},
+    storage::{content_path, Storage},
+    AppError, Chat, ChatFile, LinkPreview, Message,
+};
+
+/// previews per message, the first links win
+const MAX_PREVIEWS: usize = 3;
+const MAX_REDIRECTS: usize = 3;
+const MAX_CONCURRENT_FETCHES: usize = 8;
+const QUEUE_SIZE: usize = 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_TITLE_LEN: usize = 200;
+const MAX_DESCRIPTION_LEN: usize = 500;
+const USER_AGENT: &str = "rust-chat-unfurl/0.1";
+
+/// queue of the messages to unfurl, a no-op when unfurling is disabled
+#[derive(Debug, Clone)]
+pub(crate) struct Unfurler {
+    tx: Option<mpsc::Sender<Message>>,
+}
+
+/// fetches the previews of the queued messages and pushes them as message updates
+#[derive(Debug, Clone)]
+pub(crate) struct Worker {
+    config: UnfurlConfig,
+    pool: PgPool,
+    storage: Arc<dyn Storage>,
+}
+
+impl Unfurler {
+    /// start the worker, must be called inside the runtime
+    pub(crate) fn spawn(config: &UnfurlConfig, pool: PgPool, storage: Arc<dyn Storage>) -> Self {
+        if !config.enabled {
+            return Self { tx: None };
+        }
+        let (tx, mut rx) = mpsc::channel::<Message>(QUEUE_SIZE);
+        let worker = Worker::new(config.clone(), pool, storage);
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FETCHES));
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                    break;
+                };
+                let worker = worker.clone();
+                tokio::spawn(async move {
+                    let id = message.id;
+                    if let Err(e) = worker.unfurl(message).await {
+                        warn!("unfurl message {} failed: {}", id, e);
+                    }
+                    drop(permit);
+                });
+            }
+        });
+        Self { tx: Some(tx) }
+    }
+
+    /// best effort, the message is sent without previews when the queue is full
+    pub(crate) fn enqueue(&self, message: &Message) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if message.links.is_empty() {
+            return;
+        }
+        if let Err(e) = tx.try_send(message.clone()) {
+            warn!("unfurl queue rejected message {}: {}", message.id, e);
+        }
+    }
+}
+
+impl Worker {
+    pub(crate) fn new(config: UnfurlConfig, pool: PgPool, storage: Arc<dyn Storage>) -> Self {
+        Self { config, pool, storage }
+    }
+
+    /// attach the previews of the links of a message and notify the chat members
+    pub(crate) async fn unfurl(&self, message: Message) -> Result<Option<Message>, AppError> {
+        let mut previews = vec![];
+        for url in message.links.iter().take(MAX_PREVIEWS) {
+            let preview = match LinkPreview::find_cached(url, self.config.cache_ttl, &self.pool).await? {
+                Some(preview) => preview,
+                None => {
+                    let mut preview = self.fetch(url).await.unwrap_or_else(|e| {
+                        warn!("fetch preview of {} failed: {}", url, e);
+                        LinkPreview {
+                            url: url.clone(),
+                            ..Default::default()
+                        }
+                    });
+                    self.cache_image(&mut preview, message.sender_id).await?;
+                    preview.save(&self.pool).await?;
+                    preview
+                }
+            };
+            if !preview.is_empty() {
+                previews.push(preview);
+            }
+        }
+        if previews.is_empty() {
+            return Ok(None);
+        }
+
+        let Some(message) = Message::set_previews(message.id, &message.links, previews, &self.pool).await? else {
+            return Ok(None);
+        };
+        if let Some(chat) = Chat::get_by_id(message.chat_id, &self.pool).await? {
+            notify_message(&chat.members, &message, ChatEvent::MessageUpdated, &self.pool).await?;
+        }
+        Ok(Some(message))
+    }
+
+    /// fetch the open graph metadata of a page. Every hop of a redirect is checked, the
+    /// connection goes to the address that was checked so DNS can not be rebound meanwhile
+    pub(crate) async fn fetch(&self, url: &str) -> Result<LinkPreview, AppError> {
+        let timeout = Duration::from_secs(self.config.timeout);
+        tokio::time::timeout(timeout, self.fetch_page(url, timeout))
+            .await
+            .map_err(|_| AppError::UnfurlError(format!("fetching {} timed out", url)))?
+    }
+
+    async fn fetch_page(&self, url: &str, timeout: Duration) -> Result<LinkPreview, AppError> {
+        let (target, res) = self.get(url, "text/html", timeout).await?;
+        let is_html = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/html") || v.starts_with("application/xhtml+xml"));
+        if !is_html {
+            return Err(AppError::UnfurlError(format!("{} is not a html page", target)));
+        }
+
+        // the metadata is in the head, the rest of a large page is not needed
+        let (body, _) = read_body(res, self.config.max_size).await?;
+        let mut preview = parse_preview(&target, &String::from_utf8_lossy(&body));
+        preview.url = url.to_string();
+        Ok(preview)
+    }
+
+    /// replace the remote image of the preview by a copy in the storage, so that clients
+    /// showing the preview never connect to the linked site. The image is left out if it
+    /// can not be fetched
+    async fn cache_image(&self, preview: &mut LinkPreview, uploader_id: i64) -> Result<(), AppError> {
+        let Some(url) = preview.image.take() else {
+            return Ok(());
+        };
+        let timeout = Duration::from_secs(self.config.timeout);
+        let fetched = tokio::time::timeout(timeout, self.fetch_image(&url, timeout))
+            .await
+            .unwrap_or_else(|_| Err(AppError::UnfurlError(format!("fetching {} timed out", url))));
+        let (data, content_type) = match fetched {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("fetch preview image {} failed: {}", url, e);
+                return Ok(());
+            }
+        };
+
+        let ext = infer::get(&data).map(|t| t.extension()).unwrap_or_default();
+        let path = content_path(&data, ext);
+        let size = data.len() as i64;
+        self.storage.put(&path, data.into()).await?;
+        let file = ChatFile::create(&path, uploader_id, content_type, size, &self.pool).await?;
+        preview.image = Some(file.url());
+        Ok(())
+    }
+
+    /// the image and its sniffed type, whatever the site claims it is
+    async fn fetch_image(&self, url: &str, timeout: Duration) -> Result<(Vec<u8>, &'static str), AppError> {
+        let (target, res) = self.get(url, "image/*", timeout).await?;
+        let (data, truncated) = read_body(res, self.config.max_image_size).await?;
+        if truncated {
+            return Err(AppError::UnfurlError(format!(
+                "{} exceeds {} bytes",
+                target, self.config.max_image_size
+            )));
+        }
+        let content_type = sniff_content_type(&data);
+        if !content_type.starts_with("image/") {
+            return Err(AppError::UnfurlError(format!("{} is not an image", target)));
+        }
+        Ok((data, content_type))
+    }
+
+    /// a successful response for the url, following the redirects
+    async fn get(&self, url: &str, accept: &str, timeout: Duration) -> Result<(Url, Response), AppError> {
+        let mut target = Url::parse(url).map_err(unfurl_error)?;
+        for _ in 0..=MAX_REDIRECTS {
+            let addr = self.resolve(&target).await?;
+            let mut builder = reqwest::Client::builder()
+                .redirect(Policy::none())
+                .timeout(timeout)
+                .connect_timeout(CONNECT_TIMEOUT)
+                .user_agent(USER_AGENT);
+            if let Some(domain) = target.domain() {
+                builder = builder.resolve(domain, addr);
+            }
+            let client = builder.build().map_err(unfurl_error)?;
+            let res = client
+                .get(target.clone())
+                .header(ACCEPT, accept)
+                .send()
+                .await
+                .map_err(unfurl_error)?;
+
+            if res.status().is_redirection() {
+                let location = res
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|v| v.to_str().ok())
+                    .ok_or_else(|| AppError::UnfurlError(format!("redirect without location from {}", target)))?;
+                target = target.join(location).map_err(unfurl_error)?;
+                continue;
+            }
+            if !res.status().is_success() {
+                return Err(AppError::UnfurlError(format!("{} returned {}", target, res.status())));
+            }
+            return Ok((target, res));
+        }
+        Err(AppError::UnfurlError(format!("too many redirects from {}", url)))
+    }
+
+    /// the address to connect to, only public ones unless configured otherwise
+    async fn resolve(&self, url: &Url) -> Result<SocketAddr, AppError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AppError::UnfurlError(format!("scheme of {} is not allowed", url)));
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = match (url.domain(), url.host_str()) {
+            (Some(domain), _) => tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(unfurl_error)?
+                .collect(),
+            // ipv6 hosts are bracketed
+            (None, Some(host)) => host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .map(|ip| vec![SocketAddr::new(ip, port)])
+                .map_err(unfurl_error)?,
+            (None, None) => vec![],
+        };
+        // a name resolving to any private address is refused, whichever one we would pick
+        let allowed = !addrs.is_empty() && addrs.iter().all(|addr| self.config.allow_private || is_public(addr.ip()));
+        if !allowed {
+            return Err(AppError::UnfurlError(format!("address of {} is not allowed", url)));
+        }
+        Ok(addrs[0])
+    }
+}
+
+/// at most `max_size` bytes of the body, and whether there was more
+async fn read_body(mut res: Response, max_size: usize) -> Result<(Vec<u8>, bool), AppError> {
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await.map_err(unfurl_error)? {
+        let take = chunk.len().min(max_size - body.len());
+        body.extend_from_slice(&chunk[..take]);
+        if take < chunk.len() {
+            return Ok((body, true));
+        }
+    }
+    Ok((body, false))
+}
+
+fn unfurl_error(e: impl std::fmt::Display) -> AppError {
+    AppError::UnfurlError(e.to_string())
+}
+
+/// false for loopback, private, link local, shared (CGNAT), benchmarking and other reserved
+/// ranges. IPv6 ranges that embed or tunnel to an IPv4 address (NAT64, 6to4, IPv4-compatible)
+/// are refused as a whole, they may reach a private IPv4 network
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (b == 18 || b == 19))
+                || (a == 192 && b == 0 && c == 0))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let segments = ip.segments();
+            let first = segments[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                // NAT64, well known and local use prefixes
+                || (first == 0x64 && segments[1] == 0xff9b)
+                // 6to4
+                || first == 0x2002
+                // IPv4-compatible
+                || segments[..6].iter().all(|&s| s == 0))
+        }
+    }
+}
+
+/// open graph tags first, then twitter cards, then the plain html title and description
+fn parse_preview(url: &Url, html: &str) -> LinkPreview {
+    // ascii lowercasing keeps the byte offsets
+    let lower = html.to_ascii_lowercase();
+    let mut meta: HashMap<String, String> = HashMap::new();
+    let mut pos = 0;
+    while let Some(i) = lower[pos..].find("<meta") {
+        let start = pos + i + "<meta".len();
+        let end = lower[start..].find('>').map_or(html.len(), |j| start + j);
+        let attrs = parse_attrs(&html[start..end]);
+        let key = attrs.get("property").or_else(|| attrs.get("name"));
+        if let (Some(key), Some(content)) = (key, attrs.get("content")) {
+            meta.entry(key.to_ascii_lowercase()).or_insert_with(|| decode_entities(content));
+        }
+        pos = end;
+    }
+    let pick = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| meta.get(*key))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    let title = pick(&["og:title", "twitter:title"]).or_else(|| {
+        let start = lower.find("<title")?;
+        let start = start + lower[start..].find('>')? + 1;
+        let end = start + lower[start..].find("</title")?;
+        Some(decode_entities(html[start..end].trim())).filter(|v| !v.is_empty())
+    });
+    let image = pick(&["og:image", "og:image:url", "twitter:image"])
+        .and_then(|image| url.join(&image).ok())
+        .filter(|image| matches!(image.scheme(), "http" | "https"))
+        .map(String::from);
+
+    LinkPreview {
+        url: url.to_string(),
+        title: title.map(|v| truncate(v, MAX_TITLE_LEN)),
+        description: pick(&["og:description", "twitter:description", "description"])
+            .map(|v| truncate(v, MAX_DESCRIPTION_LEN)),
+        image,
+        site_name: pick(&["og:site_name"]).map(|v| truncate(v, MAX_TITLE_LEN)),
+    }
+}
+
+/// attributes of a tag, names lowercased
+fn parse_attrs(tag: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            break;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let Some(after_eq) = rest.strip_prefix('=') else {
+            attrs.insert(name, String::new());
+            continue;
+        };
+        rest = after_eq.trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = rest[1..].find(quote).map_or(rest.len(), |i| i + 1);
+                let value = &rest[1..end];
+                rest = rest.get(end + 1..).unwrap_or("");
+                value
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let value = &rest[..end];
+                rest = &rest[end..];
+                value
+            }
+        };
+        attrs.insert(name, value.to_string());
+    }
+    attrs
+}
+
+fn decode_entities(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn truncate(mut s: String, max: usize) -> String {
+    if let Some((i, _)) = s.char_indices().nth(max) {
+        s.truncate(i);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::Result;
+    use axum::{
+        extract::State,
+        http::{header, StatusCode},
+        response::{IntoResponse, Redirect},
+        routing::get,
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    use crate::{
+        models::{CreateChat, CreateMessage},
+        storage::LocalStorage,
+        AppConfig, AppState, User,
+    };
+
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    const PAGE: &str = r#"<html><head>
+        <title>Fallback</title>
+        <meta property="og:title" content="Release notes &amp; more">
+        <meta name="description" content='All the changes'>
+        <meta property="og:image" content="/cover.png" />
+        </head><body>hi</body></html>"#;
+
+    /// serves the test pages and counts the requests
+    async fn start_fixture() -> Result<(String, Arc<AtomicUsize>)> {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new()
+            .route("/page", get(page))
+            .route("/moved", get(|| async { Redirect::temporary("/page") }))
+            .route("/loop", get(|| async { Redirect::temporary("/loop") }))
+            .route("/image", get(|| async { ([(header::CONTENT_TYPE, "image/png")], "png") }))
+            .route("/cover.png", get(|| async { ([(header::CONTENT_TYPE, "image/png")], PNG) }))
+            .route("/huge", get(|| async { ([(header::CONTENT_TYPE, "text/html")], "x".repeat(1 << 20)) }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    StatusCode::OK
+                }),
+            )
+            .with_state(hits.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok((format!("http://{}", addr), hits))
+    }
+
+    async fn page(State(hits): State<Arc<AtomicUsize>>) -> impl IntoResponse {
+        hits.fetch_add(1, Ordering::SeqCst);
+        ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], PAGE)
+    }
+
+    fn test_config() -> UnfurlConfig {
+        UnfurlConfig {
+            timeout: 1,
+            max_size: 64 * 1024,
+            allow_private: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_preview_should_work() -> Result<()> {
+        let url = Url::parse("https://example.com/blog/post")?;
+        let preview = parse_preview(&url, PAGE);
+        assert_eq!(preview.title.as_deref(), Some("Release notes & more"));
+        assert_eq!(preview.description.as_deref(), Some("All the changes"));
+        assert_eq!(preview.image.as_deref(), Some("https://example.com/cover.png"));
+
+        let preview = parse_preview(&url, "<TITLE> Plain </TITLE><meta content=x property=og:image>");
+        assert_eq!(preview.title.as_deref(), Some("Plain"));
+        assert_eq!(preview.image.as_deref(), Some("https://example.com/blog/x"));
+
+        let preview = parse_preview(&url, r#"<meta property="og:image" content="javascript:alert(1)">"#);
+        assert!(preview.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn is_public_should_reject_private_ranges() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "::1", "fd00::1", "fe80::1", "::ffff:10.0.0.1", "198.18.0.1", "198.19.255.255", "192.0.0.170",
+            "64:ff9b::a00:1", "64:ff9b:1::1", "2002:a00:1::1", "::10.0.0.1", "::8.8.8.8",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} should not be public", ip);
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1::1", "198.20.0.1", "192.0.1.1"] {
+            assert!(is_public(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_should_follow_the_rules() -> Result<()> {
+        let (base, _) = start_fixture().await?;
+        let pool = PgPool::connect_lazy("postgres://localhost/unused")?;
+        let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new("/tmp/chat_server_unused"));
+        let worker = Worker::new(test_config(), pool.clone(), storage.clone());
+
+        let preview = worker.fetch(&format!("{}/moved", base)).await?;
+        assert_eq!(preview.url, format!("{}/moved", base));
+        assert_eq!(preview.title.as_deref(), Some("Release notes & more"));
+        assert_eq!(preview.image, Some(format!("{}/cover.png", base)));
+
+        for path in ["/loop", "/image", "/slow", "/missing"] {
+            let ret = worker.fetch(&format!("{}{}", base, path)).await;
+            assert!(matches!(ret, Err(AppError::UnfurlError(_))), "{} should fail", path);
+        }
+        // read up to max_size only
+        let preview = worker.fetch(&format!("{}/huge", base)).await?;
+        assert!(preview.is_empty());
+
+        // loopback is refused by default
+        let worker = Worker::new(UnfurlConfig::default(), pool, storage);
+        let ret = worker.fetch(&format!("{}/page", base)).await;
+        assert!(matches!(ret, Err(AppError::UnfurlError(e)) if e.contains("not allowed")));
+        let ret = worker.fetch("file:///etc/passwd").await;
+        assert!(matches!(ret, Err(AppError::UnfurlError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unfurl_should_attach_cached_previews() -> Result<()> {
+        let (base, hits) = start_fixture().await?;
+        let config = AppConfig::load()?;
+        let (_tdb, state) = AppState::new_for_test(config).await?;
+        let users = User::create_for_test(3, &state.pool).await?;
+        let chat = Chat::create(CreateChat::new("", &[users[1].id], false), users[0].id, &state.pool).await?;
+        let worker = Worker::new(test_config(), state.pool.clone(), state.storage.clone());
+
+        let content = format!("see {}/page and {}/image", base, base);
+        let message = Message::create(CreateMessage::new(&content), chat.id, users[0].id, &state.pool).await?;
+        assert_eq!(message.links.len(), 2);
+        let message = worker.unfurl(message).await?.expect("previews should be attached");
+        assert_eq!(message.previews.len(), 1);
+        assert_eq!(message.previews[0].url, format!("{}/page", base));
+
+        // readers load the copy of the image, readable by anyone
+        let image = message.previews[0].image.clone().expect("image should be cached");
+        let path = ChatFile::path_from_url(&image).expect("image should be stored");
+        assert_eq!(state.storage.get(path).await?.as_deref(), Some(PNG));
+        assert!(ChatFile::can_access(path, users[2].id, &state.pool).await?);
+        let file = ChatFile::find_by_path(path, &state.pool).await?.unwrap();
+        assert_eq!(file.content_type, "image/png");
+
+        // the second message reuses the cached previews
+        let message = Message::create(CreateMessage::new(&content), chat.id, users[1].id, &state.pool).await?;
+        let message = worker.unfurl(message).await?.unwrap();
+        assert_eq!(message.previews.len(), 1);
+        assert_eq!(message.previews[0].image.as_deref(), Some(image.as_str()));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // a body that is not an image is left out
+        let mut preview = LinkPreview {
+            image: Some(format!("{}/image", base)),
+            ..Default::default()
+        };
+        worker.cache_image(&mut preview, users[0].id).await?;
+        assert_eq!(preview.image, None);
+        Ok(())
+    }
+}
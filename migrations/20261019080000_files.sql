@@ -0,0 +1,13 @@
+-- uploaded files, content addressed by sha256. the same content uploaded by
+-- different users is stored once but recorded per uploader
+CREATE TABLE IF NOT EXISTS files (
+    path VARCHAR(128) NOT NULL,
+    uploader_id BIGINT NOT NULL REFERENCES users(id),
+    content_type VARCHAR(128) NOT NULL,
+    size BIGINT NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (path, uploader_id)
+);
+
+-- find messages referencing a file when checking access
+CREATE INDEX IF NOT EXISTS messages_images_idx ON messages USING GIN (images);
@@ -0,0 +1,11 @@
+-- metadata of images attached to messages. thumbnails are stored next to the
+-- original as <path>.t<size>.<ext>
+CREATE TABLE IF NOT EXISTS images (
+    path VARCHAR(128) PRIMARY KEY,
+    width INT NOT NULL,
+    height INT NOT NULL,
+    blurhash VARCHAR(128) NOT NULL,
+    -- [{size, width, height, url}]
+    thumbnails JSONB NOT NULL DEFAULT '[]',
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,14 @@
+-- messages can be edited by the sender, the content it replaces is kept in
+-- message_revisions
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS edited_at timestamptz;
+
+CREATE TABLE IF NOT EXISTS message_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    -- the content before the edit
+    content TEXT NOT NULL,
+    -- when the content was replaced
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_revisions_message_id_idx ON message_revisions (message_id, id);
@@ -0,0 +1,7 @@
+-- the creator administers the chat, e.g. can delete any message in it
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS owner_id BIGINT REFERENCES users(id);
+
+-- deleted messages are kept as tombstones with the content and images cleared,
+-- so that pagination by id stays stable
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS deleted_at timestamptz;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS deleted_by BIGINT REFERENCES users(id);
@@ -0,0 +1,17 @@
+-- replies live in the thread of the root message, reply_to is the message
+-- replied to which may itself be a reply
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS reply_to BIGINT REFERENCES messages(id);
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS thread_root_id BIGINT REFERENCES messages(id);
+-- kept on the root so chat lists do not need to count
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS reply_count INT NOT NULL DEFAULT 0;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS last_reply_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS messages_thread_root_id_idx ON messages (thread_root_id, id DESC);
+
+-- users notified about new replies in a thread
+CREATE TABLE IF NOT EXISTS thread_follows (
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id)
+);
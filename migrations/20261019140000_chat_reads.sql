@@ -0,0 +1,8 @@
+-- the last message each member has read in a chat
+CREATE TABLE IF NOT EXISTS chat_reads (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    last_read_id BIGINT NOT NULL,
+    updated_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
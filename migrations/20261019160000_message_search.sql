@@ -0,0 +1,7 @@
+-- full text search on the content. the simple configuration does no stemming
+-- but works for any language, messages are often mixed.
+-- angle brackets are blanked out, the parser would take `<word ...` for a tag and skip it
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_tsv tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', translate(content, '<>', '  '))) STORED;
+
+CREATE INDEX IF NOT EXISTS messages_content_tsv_idx ON messages USING GIN (content_tsv);
@@ -0,0 +1,6 @@
+-- a message is pinned at most once, the chat comes from the message
+CREATE TABLE IF NOT EXISTS message_pins (
+    message_id BIGINT PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    pinned_by BIGINT NOT NULL REFERENCES users(id),
+    pinned_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,15 @@
+-- messages to send later, a row is deleted in the same transaction that creates the message
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id BIGINT NOT NULL REFERENCES users(id),
+    content TEXT NOT NULL,
+    images TEXT[] NOT NULL DEFAULT '{}',
+    reply_to BIGINT,
+    send_at timestamptz NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    updated_at timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_send_at_idx ON scheduled_messages(send_at);
+CREATE INDEX IF NOT EXISTS scheduled_messages_sender_id_idx ON scheduled_messages(sender_id, send_at);
@@ -0,0 +1,6 @@
+-- the message quoted or forwarded by a message, a snapshot taken when it was sent
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS quote JSONB;
+
+-- find the quotes of a message when it gets deleted
+CREATE INDEX IF NOT EXISTS messages_quote_message_id_idx ON messages (((quote->>'message_id')::BIGINT))
+    WHERE quote IS NOT NULL;
@@ -0,0 +1,10 @@
+CREATE TYPE message_format AS ENUM ('plain', 'markdown');
+
+-- rendered when the message is written, clients display `html` as is
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS format message_format NOT NULL DEFAULT 'plain',
+    ADD COLUMN IF NOT EXISTS html TEXT,
+    ADD COLUMN IF NOT EXISTS links TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS code_blocks JSONB NOT NULL DEFAULT '[]';
+
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS format message_format NOT NULL DEFAULT 'plain';
@@ -0,0 +1,12 @@
+-- fetched open graph metadata, shared by every message linking the url.
+-- a row without title, description and image records a failed fetch
+CREATE TABLE IF NOT EXISTS link_previews (
+    url TEXT PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    image TEXT,
+    site_name TEXT,
+    fetched_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS previews JSONB NOT NULL DEFAULT '[]';
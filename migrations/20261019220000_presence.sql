@@ -0,0 +1,41 @@
+CREATE TYPE user_status AS ENUM ('away', 'dnd');
+
+-- the users connected to each notify_server instance, kept alive by its heartbeat.
+-- rows of a crashed instance expire on their own
+CREATE TABLE IF NOT EXISTS presence_connections (
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    node_id TEXT NOT NULL,
+    connected_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (user_id, node_id)
+);
+
+CREATE INDEX IF NOT EXISTS presence_connections_expires_at_idx ON presence_connections(expires_at);
+
+-- status set by the user, status and text are cleared together at expires_at
+CREATE TABLE IF NOT EXISTS user_statuses (
+    user_id BIGINT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    status user_status,
+    text VARCHAR(100),
+    expires_at timestamptz,
+    updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_seen_at timestamptz;
+
+-- what clients see, shared by chat_server and notify_server
+CREATE OR REPLACE VIEW user_presence AS
+SELECT
+    u.id AS user_id,
+    CASE
+        WHEN NOT EXISTS (
+            SELECT 1 FROM presence_connections c WHERE c.user_id = u.id AND c.expires_at > NOW()
+        ) THEN 'offline'
+        WHEN s.status IS NOT NULL AND (s.expires_at IS NULL OR s.expires_at > NOW()) THEN s.status::TEXT
+        ELSE 'online'
+    END AS status,
+    CASE WHEN s.expires_at IS NULL OR s.expires_at > NOW() THEN s.text END AS status_text,
+    CASE WHEN s.expires_at > NOW() THEN s.expires_at END AS status_expires_at,
+    u.last_seen_at
+FROM users u
+LEFT JOIN user_statuses s ON s.user_id = u.id;
@@ -0,0 +1,20 @@
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS title VARCHAR(64),
+    -- IANA name, e.g. Europe/Paris
+    ADD COLUMN IF NOT EXISTS timezone VARCHAR(64),
+    -- BCP 47 tag, e.g. en-US
+    ADD COLUMN IF NOT EXISTS locale VARCHAR(16),
+    -- url of the square image and its smaller variants
+    ADD COLUMN IF NOT EXISTS avatar JSONB;
+
+CREATE INDEX IF NOT EXISTS users_avatar_url_idx ON users ((avatar->>'url'));
+
+-- an email change waiting for the new address to be verified, one per user
+CREATE TABLE IF NOT EXISTS email_changes (
+    user_id BIGINT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    email VARCHAR(64) NOT NULL,
+    -- sha256 of the token sent to the new address
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
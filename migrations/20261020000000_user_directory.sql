@@ -0,0 +1,8 @@
+-- trigram matching for the user directory, trusted so the database owner can create it
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- emails are hidden in the directory unless the user shows theirs
+ALTER TABLE users ADD COLUMN IF NOT EXISTS show_email BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS users_fullname_trgm_idx ON users USING GIN (lower(fullname) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_email_trgm_idx ON users USING GIN (lower(email) gin_trgm_ops);
@@ -0,0 +1,2 @@
+-- deleted accounts are anonymized, the row stays for the messages it sent
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at timestamptz;
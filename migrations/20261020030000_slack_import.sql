@@ -0,0 +1,4 @@
+-- the conversation a chat was imported from, e.g. slack:C024BE91L, importing it again skips it
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS import_id TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS chats_import_id_idx ON chats(import_id) WHERE import_id IS NOT NULL;
@@ -0,0 +1,10 @@
+-- days messages are kept, NULL follows the server default and 0 keeps them forever
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS retention_days INT CHECK (retention_days >= 0);
+
+-- a chat on legal hold keeps everything, whatever its retention
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS legal_hold BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS legal_hold_by BIGINT REFERENCES users(id);
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS legal_hold_at timestamptz;
+
+-- the purge walks messages by age
+CREATE INDEX IF NOT EXISTS messages_created_at_idx ON messages (created_at);
@@ -0,0 +1,8 @@
+-- presigned uploads waiting for the client to confirm them. a file is only
+-- recorded once its object exists in the storage backend
+CREATE TABLE IF NOT EXISTS pending_uploads (
+    path VARCHAR(128) NOT NULL,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (path, user_id)
+);
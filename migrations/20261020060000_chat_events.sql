@@ -0,0 +1,11 @@
+-- outbox of the events pushed to clients. pg_notify payloads are capped at 8000
+-- bytes, so only the id is sent and the notify server loads the row. Events are
+-- best effort and short lived, they do not need to survive a crash
+CREATE UNLOGGED TABLE IF NOT EXISTS chat_events (
+    id BIGSERIAL PRIMARY KEY,
+    members BIGINT[] NOT NULL,
+    event JSON NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS chat_events_created_at_idx ON chat_events(created_at);
@@ -0,0 +1,13 @@
+-- chats created before owners were recorded have none: the earliest sender still in
+-- the chat is the best guess, the first member otherwise
+UPDATE chats c
+SET owner_id = COALESCE(
+    (
+        SELECT m.sender_id FROM messages m
+        WHERE m.chat_id = c.id AND m.sender_id = ANY(c.members)
+        ORDER BY m.id
+        LIMIT 1
+    ),
+    c.members[1]
+)
+WHERE c.owner_id IS NULL;
@@ -0,0 +1,2 @@
+-- unread counts, last messages and exports walk the messages of a chat by id
+CREATE INDEX IF NOT EXISTS messages_chat_id_id_idx ON messages (chat_id, id);
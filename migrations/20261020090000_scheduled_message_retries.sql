@@ -0,0 +1,6 @@
+-- a row that failed to send is retried later, and parked after too many attempts
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS failed_at timestamptz;
+
+-- files attached to a pending message must not be purged
+CREATE INDEX IF NOT EXISTS scheduled_messages_images_idx ON scheduled_messages USING GIN (images);
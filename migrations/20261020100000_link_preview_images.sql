@@ -0,0 +1,2 @@
+-- the image is a copy in the storage, readable by anyone like the page it came from
+CREATE INDEX IF NOT EXISTS link_previews_image_idx ON link_previews(image);
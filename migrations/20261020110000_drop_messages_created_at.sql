@@ -0,0 +1,3 @@
+-- the purge walks the messages of each chat by age on messages_chat_id_idx
+-- (chat_id, created_at DESC), the global index is no longer used
+DROP INDEX IF EXISTS messages_created_at_idx;
@@ -0,0 +1,71 @@
+use std::fs::File;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub presence: PresenceConfig,
+}
+
+/// only the public key is needed, tokens are issued by chat_server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthConfig {
+    pub pk: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub db_url: String,
+}
+
+/// connections are refreshed every `heartbeat` seconds, a user whose connections were
+/// not refreshed for `expiry` seconds is offline
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceConfig {
+    #[serde(default = "default_heartbeat")]
+    pub heartbeat: u64,
+    #[serde(default = "default_expiry")]
+    pub expiry: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat: default_heartbeat(),
+            expiry: default_expiry(),
+        }
+    }
+}
+
+fn default_heartbeat() -> u64 {
+    30
+}
+
+fn default_expiry() -> u64 {
+    90
+}
+
+impl AppConfig {
+    pub fn load() -> Result<Self> {
+        // read from /etc/config/notify.yml or ./notify.yml or from env NOTIFY_CONFIG
+        let ret = match (
+            File::open("notify.yml"),
+            File::open("/etc/config/notify.yml"),
+            std::env::var("NOTIFY_CONFIG"),
+        ) {
+            (Ok(file), _, _) => serde_yaml::from_reader(file)?,
+            (_, Ok(file), _) => serde_yaml::from_reader(file)?,
+            (_, _, Ok(path)) => {
+                println!("loading config from {}", path);
+                serde_yaml::from_reader(File::open(path)?)?
+            }
+            _ => bail!("no config file found"),
+        };
+        Ok(ret)
+    }
+}
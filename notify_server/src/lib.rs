@@ -1,19 +1,120 @@
+mod config;
+mod middlewares;
+mod notif;
+mod presence;
 mod sse;
+mod utils;
 
+use std::{ops::Deref, sync::Arc};
+
+use anyhow::Context;
 use axum::{
+    middleware::from_fn_with_state,
     response::{Html, IntoResponse},
     routing::get,
     Router,
 };
+use dashmap::DashMap;
+use middlewares::verify_token;
+use sqlx::PgPool;
+use serde::{Deserialize, Serialize};
 use sse::sse_handler;
+use tokio::sync::broadcast;
+use utils::DecodingKey;
+
+pub use config::AppConfig;
+pub use notif::{setup_pg_listener, AppEvent};
+pub use presence::spawn_heartbeat;
 
 const INDEX_HTML: &str = include_str!("../index.html");
-pub fn get_router() -> Router {
-    Router::new()
-        .route("/", get(index_handler))
+/// events buffered per user before a slow client starts missing them
+const CHANNEL_CAPACITY: usize = 256;
+
+/// the user in the token issued by chat_server
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub fullname: String,
+    pub email: String,
+}
+
+#[derive(Clone)]
+pub struct AppState(Arc<AppStateInner>);
+
+pub struct AppStateInner {
+    pub config: AppConfig,
+    pub(crate) dk: DecodingKey,
+    /// connected users, a user with several devices shares one channel
+    pub(crate) users: DashMap<i64, broadcast::Sender<Arc<AppEvent>>>,
+    pub(crate) pool: PgPool,
+    /// identifies the presence connections of this instance
+    pub(crate) node_id: String,
+}
+
+pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
+    let state = AppState::try_new(config)?;
+    setup_pg_listener(state.clone()).await?;
+    spawn_heartbeat(state.clone());
+
+    let app = Router::new()
         .route("/events", get(sse_handler))
+        .layer(from_fn_with_state(state.clone(), verify_token))
+        .route("/", get(index_handler))
+        .with_state(state);
+    Ok(app)
 }
 
 async fn index_handler() -> impl IntoResponse {
     Html(INDEX_HTML)
 }
+
+impl Deref for AppState {
+    type Target = AppStateInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AppState {
+    pub fn try_new(config: AppConfig) -> anyhow::Result<Self> {
+        let dk = DecodingKey::load_pem(&config.auth.pk).context("load pk failed")?;
+        let pool = PgPool::connect_lazy(&config.server.db_url).context("invalid db_url")?;
+        Ok(Self::new(config, dk, pool))
+    }
+
+    fn new(config: AppConfig, dk: DecodingKey, pool: PgPool) -> Self {
+        Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            users: DashMap::new(),
+            pool,
+            node_id: uuid::Uuid::new_v4().to_string(),
+        }))
+    }
+
+    /// receive the events of a user
+    pub(crate) fn subscribe(&self, user_id: i64) -> broadcast::Receiver<Arc<AppEvent>> {
+        self.users
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    pub async fn new_for_test(
+        config: AppConfig,
+    ) -> anyhow::Result<(sqlx_db_tester::TestPg, Self)> {
+        use sqlx_db_tester::TestPg;
+        use std::path::Path;
+
+        let dk = DecodingKey::load_pem(&config.auth.pk).context("test load pk failed")?;
+        let post = config.server.db_url.rfind('/').expect("invalid db_url");
+        let server_url = String::from(&config.server.db_url[..post]) + "/test";
+        let tdb = TestPg::new(server_url, Path::new("../migrations"));
+        let pool = tdb.get_pool().await;
+        Ok((tdb, Self::new(config, dk, pool)))
+    }
+}
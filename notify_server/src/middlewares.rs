@@ -0,0 +1,73 @@
+use axum::{
+    extract::{FromRequestParts, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+struct Params {
+    token: String,
+}
+
+/// same as chat_server, browsers can not set headers on EventSource so the token
+/// is usually passed in the query
+pub(crate) async fn verify_token(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let token = match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
+        Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+        Err(e) if e.is_missing() => match Query::<Params>::from_request_parts(&mut parts, &state).await {
+            Ok(params) => params.0.token,
+            Err(e) => {
+                let msg = format!("parse query params failed: {}", e);
+                warn!(msg);
+                return (StatusCode::UNAUTHORIZED, msg).into_response();
+            }
+        },
+        Err(e) => {
+            let msg = format!("parse Authorization header failed: {}", e);
+            warn!(msg);
+            return (StatusCode::UNAUTHORIZED, msg).into_response();
+        }
+    };
+
+    match state.dk.verify(&token) {
+        Ok(user) => {
+            // tokens outlive the account, deleting it revokes them
+            let deleted = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NOT NULL)",
+            )
+            .bind(user.id)
+            .fetch_one(&state.pool)
+            .await;
+            match deleted {
+                Ok(false) => {}
+                Ok(true) => {
+                    let msg = format!("user {} was deleted", user.id);
+                    warn!(msg);
+                    return (StatusCode::FORBIDDEN, msg).into_response();
+                }
+                Err(e) => {
+                    warn!("check user {} failed: {}", user.id, e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            }
+            let mut req = Request::from_parts(parts, body);
+            req.extensions_mut().insert(user);
+            next.run(req).await
+        }
+        Err(e) => {
+            let msg = format!("verify token failed: {:?}", e);
+            warn!(msg);
+            (StatusCode::FORBIDDEN, msg).into_response()
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+/// postgres channel chat_server publishes to, the payload is the id of a `chat_events` row
+/// or, for small ephemeral events, an [`InlineEvent`]
+pub(crate) const NOTIFY_CHANNEL: &str = "chat_events";
+
+/// sent when the account of a user was deleted, their streams are closed after it
+const SESSIONS_REVOKED: &str = "sessions_revoked";
+
+/// an event sent to the clients, `name` is the `type` chat_server tagged it with and
+/// `data` the json as is
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppEvent {
+    pub name: String,
+    pub data: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventType {
+    r#type: String,
+}
+
+/// an event carried by the notification itself, never stored
+#[derive(Debug, Deserialize)]
+struct InlineEvent {
+    members: Vec<i64>,
+    event: serde_json::Value,
+}
+
+pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
+    let mut listener = PgListener::connect(&state.config.server.db_url).await?;
+    listener.listen(NOTIFY_CHANNEL).await?;
+    info!("listening to {}", NOTIFY_CHANNEL);
+
+    tokio::spawn(async move {
+        loop {
+            // the listener reconnects on the next recv when the connection is lost
+            match listener.recv().await {
+                Ok(notification) => {
+                    if let Err(e) = state.dispatch_notification(notification.payload()).await {
+                        warn!("invalid notification {}: {}", notification.payload(), e);
+                    }
+                }
+                Err(e) => {
+                    warn!("receive notification failed: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+impl AppState {
+    /// load the event the notification points to, then dispatch it
+    async fn dispatch_notification(&self, payload: &str) -> anyhow::Result<()> {
+        if payload.starts_with('{') {
+            let InlineEvent { members, event } = serde_json::from_str(payload)?;
+            self.dispatch(&members, event.to_string())?;
+            return Ok(());
+        }
+        let id: i64 = payload.parse()?;
+        let event: Option<(Vec<i64>, String)> =
+            sqlx::query_as("SELECT members, event::TEXT FROM chat_events WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        // pruned before we got to it, the clients catch up on their next fetch
+        let Some((members, event)) = event else {
+            warn!("event {} is gone", id);
+            return Ok(());
+        };
+        self.dispatch(&members, event)?;
+        Ok(())
+    }
+
+    /// send the event to the connected members
+    fn dispatch(&self, members: &[i64], event: String) -> serde_json::Result<()> {
+        let EventType { r#type } = serde_json::from_str(&event)?;
+        let revoked = r#type == SESSIONS_REVOKED;
+        let event = Arc::new(AppEvent {
+            name: r#type,
+            data: event,
+        });
+
+        for &user_id in members {
+            let disconnected = match self.users.get(&user_id) {
+                Some(tx) => tx.send(event.clone()).is_err(),
+                None => false,
+            };
+            if revoked {
+                // dropping the sender ends the streams once they got the event
+                self.users.remove(&user_id);
+            } else if disconnected {
+                self.users.remove_if(&user_id, |_, tx| tx.receiver_count() == 0);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use tokio::sync::broadcast;
+
+    use crate::AppConfig;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn dispatch_should_reach_connected_members() -> Result<()> {
+        let state = AppState::try_new(AppConfig::load()?)?;
+        let mut rx1 = state.subscribe(1);
+        let mut rx3 = state.subscribe(3);
+
+        let event = r#"{"type":"message_updated","id":7}"#;
+        state.dispatch(&[1, 2], event.to_string())?;
+        let received = rx1.try_recv()?;
+        assert_eq!(received.name, "message_updated");
+        assert_eq!(received.data, event);
+        assert!(rx3.try_recv().is_err());
+
+        // channels of disconnected users are dropped
+        drop(rx1);
+        state.dispatch(&[1, 2], event.to_string())?;
+        assert!(!state.users.contains_key(&1));
+
+        assert!(state.dispatch(&[1], r#"{"id":7}"#.to_string()).is_err());
+
+        // revoked sessions get the event, then their stream ends
+        state.dispatch(
+            &[3],
+            r#"{"type":"sessions_revoked","user_id":3}"#.to_string(),
+        )?;
+        assert_eq!(rx3.try_recv()?.name, "sessions_revoked");
+        assert!(matches!(rx3.try_recv(), Err(broadcast::error::TryRecvError::Closed)));
+        assert!(!state.users.contains_key(&3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dispatch_notification_should_load_the_event() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let mut rx = state.subscribe(1);
+        let id: i64 = sqlx::query_scalar(
+            r#"INSERT INTO chat_events (members, event) VALUES ('{1}', '{"type":"typing"}') RETURNING id"#,
+        )
+        .fetch_one(&state.pool)
+        .await?;
+
+        state.dispatch_notification(&id.to_string()).await?;
+        assert_eq!(rx.try_recv()?.name, "typing");
+        // already pruned
+        state.dispatch_notification(&(id + 1).to_string()).await?;
+        assert!(state.dispatch_notification("oops").await.is_err());
+
+        // small events come inline
+        let payload = r#"{"members":[1],"event":{"type":"typing","chat_id":2}}"#;
+        state.dispatch_notification(payload).await?;
+        assert_eq!(rx.try_recv()?.name, "typing");
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use std::time::Duration;
+
+use sqlx::{PgExecutor, PgPool};
+use tracing::warn;
+
+use crate::{notif::NOTIFY_CHANNEL, AppState};
+
+/// keeps a user online while the client is connected, the user goes offline when the
+/// last connection to this instance is dropped
+pub(crate) struct Connection {
+    state: AppState,
+    user_id: i64,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let state = self.state.clone();
+        let user_id = self.user_id;
+        tokio::spawn(async move {
+            if let Err(e) = state.disconnect(user_id).await {
+                warn!("disconnect user {} failed: {}", user_id, e);
+            }
+        });
+    }
+}
+
+/// refresh the connections of this instance every `heartbeat` seconds, expire the
+/// connections of the instances that stopped doing so and the expired user statuses
+pub fn spawn_heartbeat(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(state.config.presence.heartbeat.max(1)));
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.heartbeat().await {
+                warn!("presence heartbeat failed: {}", e);
+            }
+        }
+    });
+}
+
+impl AppState {
+    /// mark the user as connected to this instance, must be called after `subscribe`
+    pub(crate) async fn connect(&self, user_id: i64) -> Connection {
+        if let Err(e) = self.refresh(user_id).await {
+            warn!("connect user {} failed: {}", user_id, e);
+        }
+        Connection {
+            state: self.clone(),
+            user_id,
+        }
+    }
+
+    async fn refresh(&self, user_id: i64) -> Result<(), sqlx::Error> {
+        let was_online = is_online(user_id, &self.pool).await?;
+        self.keep_alive(&[user_id]).await?;
+        if !was_online {
+            publish(user_id, &self.pool).await?;
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self, user_id: i64) -> Result<(), sqlx::Error> {
+        // other clients of the user are still connected to this instance
+        if self.users.remove_if(&user_id, |_, tx| tx.receiver_count() == 0).is_none()
+            && self.users.contains_key(&user_id)
+        {
+            return Ok(());
+        }
+
+        sqlx::query("DELETE FROM presence_connections WHERE user_id = $1 AND node_id = $2")
+            .bind(user_id)
+            .bind(&self.node_id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("UPDATE users SET last_seen_at = NOW() WHERE id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        // the user may still be connected to another instance
+        if !is_online(user_id, &self.pool).await? {
+            publish(user_id, &self.pool).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn heartbeat(&self) -> Result<(), sqlx::Error> {
+        let connected: Vec<i64> = self
+            .users
+            .iter()
+            .filter(|entry| entry.value().receiver_count() > 0)
+            .map(|entry| *entry.key())
+            .collect();
+        // also restores a row a concurrent disconnect deleted
+        self.keep_alive(&connected).await?;
+
+        let expired: Vec<i64> = sqlx::query_scalar(
+            r#"
+            DELETE FROM presence_connections
+            WHERE expires_at <= NOW()
+            RETURNING user_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut changed = Vec::new();
+        for user_id in expired {
+            if !changed.contains(&user_id) && !is_online(user_id, &self.pool).await? {
+                changed.push(user_id);
+            }
+        }
+
+        // statuses users set with an expiry, the view already hides them
+        let cleared: Vec<i64> = sqlx::query_scalar(
+            r#"
+            DELETE FROM user_statuses
+            WHERE expires_at <= NOW()
+            RETURNING user_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for user_id in cleared {
+            if !changed.contains(&user_id) {
+                changed.push(user_id);
+            }
+        }
+        // the rows are gone already, a failing user must not cost the others their event
+        for user_id in changed {
+            if let Err(e) = publish(user_id, &self.pool).await {
+                warn!("publish presence of user {} failed: {}", user_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn keep_alive(&self, user_ids: &[i64]) -> Result<(), sqlx::Error> {
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+        // users deleted meanwhile are skipped
+        sqlx::query(
+            r#"
+            INSERT INTO presence_connections (user_id, node_id, expires_at)
+            SELECT id, $2, NOW() + make_interval(secs => $3)
+            FROM users
+            WHERE id = ANY($1)
+            ON CONFLICT (user_id, node_id) DO UPDATE SET expires_at = EXCLUDED.expires_at
+            "#,
+        )
+        .bind(user_ids)
+        .bind(&self.node_id)
+        .bind(self.config.presence.expiry as f64)
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("UPDATE users SET last_seen_at = NOW() WHERE id = ANY($1)")
+            .bind(user_ids)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn is_online(user_id: i64, pool: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM presence_connections WHERE user_id = $1 AND expires_at > NOW()
+        )
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// send the presence of the user to themselves and to everyone sharing a chat with them,
+/// the same way chat_server publishes its events
+async fn publish<'e>(user_id: i64, executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH events AS (
+            INSERT INTO chat_events (members, event)
+            SELECT
+                ARRAY(
+                    SELECT unnest(c.members) FROM chats c WHERE p.user_id = ANY(c.members)
+                    UNION
+                    SELECT p.user_id
+                ),
+                (to_jsonb(p) || '{"type": "presence_changed"}')::JSON
+            FROM user_presence p
+            WHERE p.user_id = $2
+            RETURNING id
+        )
+        SELECT pg_notify($1, id::TEXT) FROM events
+        "#,
+    )
+    .bind(NOTIFY_CHANNEL)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::Value;
+    use sqlx::postgres::PgListener;
+
+    use crate::AppConfig;
+
+    use super::*;
+
+    async fn next_event(listener: &mut PgListener, pool: &PgPool) -> Result<Value> {
+        let notification =
+            tokio::time::timeout(Duration::from_secs(5), listener.recv()).await??;
+        let id: i64 = notification.payload().parse()?;
+        let event: String = sqlx::query_scalar(
+            "SELECT json_build_object('members', members, 'event', event)::TEXT FROM chat_events WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+        Ok(serde_json::from_str(&event)?)
+    }
+
+    #[tokio::test]
+    async fn presence_should_follow_connections() -> Result<()> {
+        let (tdb, state) = AppState::new_for_test(AppConfig::load()?).await?;
+        let pool = tdb.get_pool().await;
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (fullname, email, password_hash)
+            SELECT 'user' || n, 'user' || n || '@acme.org', ''
+            FROM generate_series(1, 3) n
+            RETURNING id
+            "#,
+        )
+        .fetch_all(&pool)
+        .await?;
+        sqlx::query("INSERT INTO chats (name, type, members) VALUES ('', 'single', $1)")
+            .bind(&ids[..2])
+            .execute(&pool)
+            .await?;
+
+        let mut listener = PgListener::connect_with(&pool).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+
+        let rx = state.subscribe(ids[0]);
+        let connection = state.connect(ids[0]).await;
+        let ret = next_event(&mut listener, &pool).await?;
+        let mut members: Vec<i64> = serde_json::from_value(ret["members"].clone())?;
+        members.sort();
+        assert_eq!(members, ids[..2]);
+        assert_eq!(ret["event"]["type"], "presence_changed");
+        assert_eq!(ret["event"]["user_id"], ids[0]);
+        assert_eq!(ret["event"]["status"], "online");
+        assert!(ret["event"]["last_seen_at"].is_string());
+
+        // a second client of the same user changes nothing
+        let rx2 = state.subscribe(ids[0]);
+        drop(state.connect(ids[0]).await);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drop(rx2);
+        drop(rx);
+        drop(connection);
+        let ret = next_event(&mut listener, &pool).await?;
+        assert_eq!(ret["event"]["status"], "offline");
+        assert!(!state.users.contains_key(&ids[0]));
+
+        // connections of a crashed instance expire
+        sqlx::query(
+            r#"
+            INSERT INTO presence_connections (user_id, node_id, expires_at)
+            VALUES ($1, 'crashed', NOW() - INTERVAL '1 second')
+            "#,
+        )
+        .bind(ids[2])
+        .execute(&pool)
+        .await?;
+        state.heartbeat().await?;
+        let ret = next_event(&mut listener, &pool).await?;
+        assert_eq!(ret["members"], serde_json::json!([ids[2]]));
+        assert_eq!(ret["event"]["status"], "offline");
+
+        // so do the statuses users set
+        sqlx::query(
+            r#"
+            INSERT INTO user_statuses (user_id, status, expires_at)
+            VALUES ($1, 'away', NOW() - INTERVAL '1 second')
+            "#,
+        )
+        .bind(ids[1])
+        .execute(&pool)
+        .await?;
+        state.heartbeat().await?;
+        let ret = next_event(&mut listener, &pool).await?;
+        assert_eq!(ret["event"]["user_id"], ids[1]);
+        assert_eq!(ret["event"]["status_text"], Value::Null);
+        Ok(())
+    }
+}
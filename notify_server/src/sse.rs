@@ -1,25 +1,43 @@
-use axum::response::{sse::Event, Sse};
-use axum_extra::{headers::UserAgent, TypedHeader};
-use futures::stream::{self, Stream};
 use std::{convert::Infallible, time::Duration};
-use tokio_stream::StreamExt as _;
+
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive},
+        Sse,
+    },
+    Extension,
+};
+use futures::Stream;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt as _};
+use tracing::{info, warn};
+
+use crate::{AppState, User};
 
 pub(crate) async fn sse_handler(
-    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    println!("`{}` connected", user_agent.as_str());
+    info!("user {} connected", user.id);
 
-    // A `Stream` that repeats an event every second
-    //
-    // You can also create streams from tokio channels using the wrappers in
-    // https://docs.rs/tokio-stream
-    let stream = stream::repeat_with(|| Event::default().data("hi!"))
-        .map(Ok)
-        .throttle(Duration::from_secs(1));
+    let rx = state.subscribe(user.id);
+    let connection = state.connect(user.id).await;
+    let stream = BroadcastStream::new(rx).filter_map(move |event| {
+        // the user stays online as long as the stream is alive
+        let _ = &connection;
+        match event {
+            Ok(event) => Some(Ok(Event::default().event(&event.name).data(&event.data))),
+            // the client refetches what it missed when it sees a gap
+            Err(e) => {
+                warn!("user {} lagged: {}", user.id, e);
+                None
+            }
+        }
+    });
 
     Sse::new(stream).keep_alive(
-        axum::response::sse::KeepAlive::new()
-            .interval(Duration::from_secs(1))
-            .text("keep-alive-text"),
+        KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("keep-alive"),
     )
 }
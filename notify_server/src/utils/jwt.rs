@@ -0,0 +1,35 @@
+use std::ops::Deref;
+
+use jwt_simple::prelude::*;
+
+use crate::User;
+
+const JWT_ISS: &str = "chat_server";
+const JWT_AUD: &str = "chat_web";
+
+pub struct DecodingKey(Ed25519PublicKey);
+
+impl DecodingKey {
+    pub fn load_pem(pem: &str) -> Result<Self, jwt_simple::Error> {
+        Ok(Self(Ed25519PublicKey::from_pem(pem)?))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
+        let opts = VerificationOptions {
+            allowed_issuers: Some(HashSet::from_strings(&[JWT_ISS])),
+            allowed_audiences: Some(HashSet::from_strings(&[JWT_AUD])),
+            ..Default::default()
+        };
+        let claims = self.verify_token::<User>(token, Some(opts))?;
+
+        Ok(claims.custom)
+    }
+}
+
+impl Deref for DecodingKey {
+    type Target = Ed25519PublicKey;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}